@@ -0,0 +1,136 @@
+//!
+//! The definitions found in AIDL files.
+//!
+
+/// A parsed `.aidl` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    /// The package, e.g. `["android", "os"]`.
+    pub package: Vec<String>,
+    /// The fully qualified names of the imported types.
+    pub imports: Vec<Vec<String>>,
+    pub definitions: Vec<Definition>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Definition {
+    Interface(Interface),
+    Parcelable(Parcelable),
+    Enum(Enum)
+}
+
+impl Definition {
+    pub fn name(&self) -> &str {
+        match *self {
+            Definition::Interface(ref i) => &i.name,
+            Definition::Parcelable(ref p) => &p.name,
+            Definition::Enum(ref e) => &e.name
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    /// Every method of a `oneway interface` is one-way.
+    pub oneway: bool,
+    pub constants: Vec<Constant>,
+    pub methods: Vec<Method>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub ty: Type,
+    pub name: String,
+    pub value: Value
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    pub oneway: bool,
+    pub ret: Option<Type>,
+    pub name: String,
+    pub args: Vec<Arg>,
+    /// The id given with `= N`, if any.
+    pub id: Option<u32>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+    InOut
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arg {
+    pub direction: Direction,
+    pub ty: Type,
+    pub name: String
+}
+
+/// A structured parcelable. Parcelables that are only declared (e.g.
+/// `parcelable Foo;`) have no fields, and are expected to be implemented by
+/// hand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parcelable {
+    pub name: String,
+    /// None for declared-only parcelables.
+    pub fields: Option<Vec<Field>>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub ty: Type,
+    pub name: String,
+    pub default: Option<Value>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    pub name: String,
+    /// The backing type given with `@Backing(type="...")`. Defaults to byte,
+    /// like AIDL does.
+    pub backing: Primitive,
+    pub enumerators: Vec<(String, i64)>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Boolean,
+    Byte,
+    Char,
+    Int,
+    Long,
+    Float,
+    Double
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeKind {
+    Primitive(Primitive),
+    String,
+    IBinder,
+    /// `T[]` and `List<T>`.
+    Array(Box<Type>),
+    /// A user-defined type, as written in the file.
+    Named(Vec<String>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Type {
+    pub kind: TypeKind,
+    /// Whether the type was annotated with `@nullable`.
+    pub nullable: bool
+}
+
+/// A constant value, as found in constant declarations and default field
+/// values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    String(String)
+}
@@ -0,0 +1,709 @@
+//!
+//! Rust code generation.
+//!
+//! Every AIDL definition gets its own module, named after it, inside modules
+//! mirroring its package. For instance, `android.os.IFoo` ends up in
+//! `android::os::IFoo`, which contains :
+//!
+//! - `IFoo`, the trait describing the interface,
+//! - `BpFoo`, the proxy implementing it over a remote `Handle`,
+//! - `BnFoo`, the stub dispatching incoming transactions to a local
+//!   implementation of `IFoo`.
+//!
+//! Parcelables and enums get a struct or enum of the same name, implementing
+//! `Parcelable`.
+//!
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use ast::*;
+use Error;
+
+/// What a qualified name refers to.
+#[derive(Debug, Clone)]
+enum Resolved {
+    Interface,
+    /// The rust path of a parcelable or enum, and the backing type of enums.
+    Path(String, Option<Primitive>)
+}
+
+/// Generates the code for a set of documents.
+pub struct Generator<'a> {
+    documents: &'a [Document],
+    /// The definitions of all the documents, indexed by qualified name.
+    definitions: HashMap<Vec<String>, &'a Definition>,
+    /// Rust paths of types implemented outside of the generated code.
+    type_map: &'a HashMap<Vec<String>, String>
+}
+
+/// The modules of the generated file.
+#[derive(Default)]
+struct Module {
+    children: BTreeMap<String, Module>,
+    code: Vec<String>
+}
+
+impl Module {
+    fn insert(&mut self, path: &[String], code: String) {
+        match path.split_first() {
+            Some((first, rest)) => self.children.entry(first.clone()).or_default().insert(rest, code),
+            None => self.code.push(code)
+        }
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        for code in &self.code {
+            for line in code.lines() {
+                if line.is_empty() {
+                    out.push('\n');
+                } else {
+                    let _ = writeln!(out, "{:width$}{}", "", line, width = indent);
+                }
+            }
+        }
+        for (name, child) in &self.children {
+            let _ = writeln!(out, "{:width$}#[allow(non_snake_case)]", "", width = indent);
+            let _ = writeln!(out, "{:width$}pub mod {} {{", "", name, width = indent);
+            child.write(out, indent + 4);
+            let _ = writeln!(out, "{:width$}}}", "", width = indent);
+        }
+    }
+}
+
+/// State for the definition currently being generated.
+struct Context<'a> {
+    document: &'a Document,
+    /// The amount of `super::` needed to get to the root of the generated
+    /// code.
+    depth: usize
+}
+
+const KEYWORDS : &[&str] = &[
+    "abstract", "alignof", "as", "become", "box", "break", "const", "continue",
+    "crate", "do", "else", "enum", "extern", "false", "final", "fn", "for",
+    "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut",
+    "offsetof", "override", "priv", "proc", "pub", "pure", "ref", "return",
+    "self", "sizeof", "static", "struct", "super", "trait", "true", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield"
+];
+
+/// Escape identifiers that happen to be rust keywords.
+fn escape(ident: &str) -> String {
+    if KEYWORDS.contains(&ident) {
+        format!("{}_", ident)
+    } else {
+        ident.to_owned()
+    }
+}
+
+/// Turn a camelCase AIDL name into a snake_case one.
+fn snake_case(ident: &str) -> String {
+    let chars : Vec<char> = ident.chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let prev_lower = i > 0 && (chars[i - 1].is_lowercase() || chars[i - 1].is_ascii_digit());
+            let next_lower = i > 0 && chars.get(i + 1).is_some_and(|c| c.is_lowercase()) && chars[i - 1].is_uppercase();
+            if prev_lower || next_lower {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    escape(&out)
+}
+
+/// Strip the leading `I` of an interface name, like AIDL does to name its
+/// proxy and stub.
+fn base_name(name: &str) -> &str {
+    if name.len() > 1 && name.starts_with('I') && name[1..].starts_with(char::is_uppercase) {
+        &name[1..]
+    } else {
+        name
+    }
+}
+
+fn primitive_type(primitive: Primitive) -> &'static str {
+    match primitive {
+        Primitive::Boolean => "bool",
+        Primitive::Byte => "i8",
+        Primitive::Char => "u16",
+        Primitive::Int => "i32",
+        Primitive::Long => "i64",
+        Primitive::Float => "f32",
+        Primitive::Double => "f64"
+    }
+}
+
+/// Whether a rust integer literal fits in a primitive.
+fn int_fits(val: i64, primitive: Primitive) -> bool {
+    match primitive {
+        Primitive::Byte => val >= i8::MIN as i64 && val <= i8::MAX as i64,
+        Primitive::Char => val >= 0 && val <= u16::MAX as i64,
+        Primitive::Int => val >= i32::MIN as i64 && val <= i32::MAX as i64,
+        _ => true
+    }
+}
+
+impl<'a> Generator<'a> {
+    pub fn new(documents: &'a [Document], type_map: &'a HashMap<Vec<String>, String>) -> Result<Generator<'a>, Error> {
+        let mut definitions = HashMap::new();
+        for document in documents {
+            for definition in &document.definitions {
+                let mut name = document.package.clone();
+                name.push(definition.name().to_owned());
+                // Hand-written parcelables are only declared, and may be
+                // declared more than once.
+                let declared_only = matches!(*definition, Definition::Parcelable(Parcelable { fields: None, .. }));
+                if let Some(previous) = definitions.insert(name.clone(), definition) {
+                    let previous_declared_only = matches!(*previous, Definition::Parcelable(Parcelable { fields: None, .. }));
+                    if declared_only {
+                        definitions.insert(name, previous);
+                    } else if !previous_declared_only {
+                        return Err(Error::generate(format!("{} is defined more than once", name.join("."))))
+                    }
+                }
+            }
+        }
+        Ok(Generator {
+            documents: documents,
+            definitions: definitions,
+            type_map: type_map
+        })
+    }
+
+    /// Generate the code for all the documents.
+    pub fn generate(&self) -> Result<String, Error> {
+        let mut root = Module::default();
+        for document in self.documents {
+            for definition in &document.definitions {
+                let ctx = Context {
+                    document: document,
+                    depth: document.package.len() + 1
+                };
+                let code = match *definition {
+                    Definition::Interface(ref interface) => self.interface(&ctx, interface)?,
+                    Definition::Parcelable(Parcelable { fields: None, .. }) => continue,
+                    Definition::Parcelable(ref parcelable) => self.parcelable(&ctx, parcelable)?,
+                    Definition::Enum(ref enumeration) => self.enumeration(enumeration)
+                };
+                let mut path = document.package.clone();
+                path.push(definition.name().to_owned());
+                root.insert(&path, code);
+            }
+        }
+        let mut out = String::from("// Generated by binder-aidl. Do not edit.\n\n");
+        root.write(&mut out, 0);
+        Ok(out)
+    }
+
+    /// Find what a type name used in `ctx` refers to.
+    fn resolve(&self, ctx: &Context, name: &[String]) -> Result<Resolved, Error> {
+        let qualified = if name.len() > 1 {
+            name.to_vec()
+        } else {
+            match ctx.document.imports.iter().find(|import| import.last().map(|s| &**s) == Some(&*name[0])) {
+                Some(import) => import.clone(),
+                None => {
+                    let mut qualified = ctx.document.package.clone();
+                    qualified.push(name[0].clone());
+                    qualified
+                }
+            }
+        };
+        if let Some(path) = self.type_map.get(&qualified) {
+            let backing = match self.definitions.get(&qualified) {
+                Some(Definition::Enum(e)) => Some(e.backing),
+                _ => None
+            };
+            return Ok(Resolved::Path(path.clone(), backing))
+        }
+        let root = "super::".repeat(ctx.depth);
+        let path = format!("{}{}::{}", root, qualified.join("::"), qualified.last().unwrap());
+        match self.definitions.get(&qualified) {
+            Some(Definition::Interface(_)) => Ok(Resolved::Interface),
+            Some(Definition::Enum(e)) => Ok(Resolved::Path(path, Some(e.backing))),
+            Some(Definition::Parcelable(Parcelable { fields: Some(_), .. })) => Ok(Resolved::Path(path, None)),
+            Some(Definition::Parcelable(Parcelable { fields: None, .. })) =>
+                Err(Error::generate(format!("{} is implemented by hand, its rust path has to be given with Builder::map_type", qualified.join(".")))),
+            None => Err(Error::generate(format!("unknown type {}", qualified.join("."))))
+        }
+    }
+
+    /// The rust type corresponding to an AIDL type.
+    fn rust_type(&self, ctx: &Context, ty: &Type) -> Result<String, Error> {
+        let base = match ty.kind {
+            TypeKind::Primitive(primitive) => primitive_type(primitive).to_owned(),
+            TypeKind::String => "String".to_owned(),
+            TypeKind::IBinder => "::binder::IBinder".to_owned(),
+            // Byte arrays are sent packed, which is how Vec<u8> is sent.
+            TypeKind::Array(ref inner) if inner.kind == TypeKind::Primitive(Primitive::Byte) => "Vec<u8>".to_owned(),
+            TypeKind::Array(ref inner) => format!("Vec<{}>", self.rust_type(ctx, inner)?),
+            // Interfaces are sent as plain binders.
+            TypeKind::Named(ref name) => match self.resolve(ctx, name)? {
+                Resolved::Interface => "::binder::IBinder".to_owned(),
+                Resolved::Path(path, _) => path
+            }
+        };
+        if ty.nullable {
+            Ok(format!("Option<{}>", base))
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// The rust type of a parcelable field. Binders don't have a default
+    /// value, so they are always nullable, like `sp<IBinder>` fields of the
+    /// C++ backend.
+    fn field_type(&self, ctx: &Context, ty: &Type) -> Result<String, Error> {
+        if !ty.nullable && self.is_binder(ctx, ty)? {
+            let mut ty = ty.clone();
+            ty.nullable = true;
+            return self.rust_type(ctx, &ty)
+        }
+        self.rust_type(ctx, ty)
+    }
+
+    /// Whether values of this type are sent as binders.
+    fn is_binder(&self, ctx: &Context, ty: &Type) -> Result<bool, Error> {
+        match ty.kind {
+            TypeKind::IBinder => Ok(true),
+            TypeKind::Named(ref name) => match self.resolve(ctx, name)? {
+                Resolved::Interface => Ok(true),
+                _ => Ok(false)
+            },
+            _ => Ok(false)
+        }
+    }
+
+    /// Whether arguments of this type are passed by value rather than by
+    /// reference.
+    fn is_copy(&self, ctx: &Context, ty: &Type) -> Result<bool, Error> {
+        if ty.nullable {
+            return Ok(false)
+        }
+        match ty.kind {
+            TypeKind::Primitive(_) => Ok(true),
+            TypeKind::Named(ref name) => match self.resolve(ctx, name)? {
+                Resolved::Path(_, Some(_)) => Ok(true),
+                _ => Ok(false)
+            },
+            _ => Ok(false)
+        }
+    }
+
+    fn is_array(ty: &Type) -> bool {
+        matches!(ty.kind, TypeKind::Array(_))
+    }
+
+    /// A rust expression for a constant value of type `ty`.
+    fn value(&self, ctx: &Context, ty: &Type, value: &Value) -> Result<String, Error> {
+        let expr = match (&ty.kind, value) {
+            (&TypeKind::Primitive(Primitive::Boolean), &Value::Bool(b)) => format!("{}", b),
+            (&TypeKind::Primitive(Primitive::Char), &Value::Char(c)) if (c as u32) <= u16::MAX as u32 =>
+                format!("{}u16", c as u32),
+            (&TypeKind::Primitive(p), &Value::Int(i)) if p != Primitive::Boolean => {
+                let rust = primitive_type(p);
+                if p == Primitive::Float || p == Primitive::Double {
+                    format!("{}.0{}", i, rust)
+                } else if int_fits(i, p) {
+                    format!("{}{}", i, rust)
+                } else {
+                    // Hex literals may overflow, like in java.
+                    format!("({}i64 as {})", i, rust)
+                }
+            },
+            (&TypeKind::Primitive(Primitive::Float), &Value::Float(f)) => format!("{:?}f32", f),
+            (&TypeKind::Primitive(Primitive::Double), &Value::Float(f)) => format!("{:?}f64", f),
+            (TypeKind::String, Value::String(s)) => format!("{:?}", s),
+            _ => return Err(Error::generate(format!("invalid value {:?} for type {}", value, self.rust_type(ctx, ty)?)))
+        };
+        Ok(expr)
+    }
+
+    fn interface(&self, ctx: &Context, interface: &Interface) -> Result<String, Error> {
+        let name = &interface.name;
+        let base = base_name(name);
+        let mut descriptor = ctx.document.package.clone();
+        descriptor.push(name.clone());
+        let mut out = String::new();
+
+        let _ = writeln!(out, "#![allow(non_upper_case_globals, unused_mut)]\n");
+        let _ = writeln!(out, "pub const DESCRIPTOR : &'static str = {:?};\n", descriptor.join("."));
+
+        for constant in &interface.constants {
+            let ty = match constant.ty.kind {
+                TypeKind::String => "&'static str".to_owned(),
+                _ => self.rust_type(ctx, &constant.ty)?
+            };
+            let _ = writeln!(out, "pub const {} : {} = {};", escape(&constant.name), ty, self.value(ctx, &constant.ty, &constant.value)?);
+        }
+        if !interface.constants.is_empty() {
+            out.push('\n');
+        }
+
+        let mut next_id = 0;
+        for method in &interface.methods {
+            let id = method.id.unwrap_or(next_id);
+            next_id = id + 1;
+            let _ = writeln!(out, "pub const TRANSACTION_{} : u32 = ::binder::FIRST_CALL_TRANSACTION + {};", method.name, id);
+        }
+        if !interface.methods.is_empty() {
+            out.push('\n');
+        }
+
+        // The interface trait.
+        let _ = writeln!(out, "pub trait {} {{", name);
+        for method in &interface.methods {
+            let _ = writeln!(out, "    fn {};", self.signature(ctx, method, false)?);
+        }
+        let _ = writeln!(out, "}}\n");
+
+        // The proxy.
+        let _ = writeln!(out, "/// Proxy for a remote `{}`.", name);
+        let _ = writeln!(out, "pub struct Bp{} {{", base);
+        let _ = writeln!(out, "    handle: ::std::rc::Rc<::std::cell::RefCell<::binder::Handle>>");
+        let _ = writeln!(out, "}}\n");
+        let _ = writeln!(out, "impl Bp{} {{", base);
+        let _ = writeln!(out, "    pub fn as_binder(&self) -> ::binder::IBinder {{");
+        let _ = writeln!(out, "        ::binder::IBinder::Remote(self.handle.clone())");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+        let _ = writeln!(out, "impl ::binder::IInterface for Bp{} {{", base);
+        let _ = writeln!(out, "    fn get_interface_descriptor() -> &'static str {{");
+        let _ = writeln!(out, "        DESCRIPTOR");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "    fn from_handle(handle: ::std::rc::Rc<::std::cell::RefCell<::binder::Handle>>) -> Bp{} {{", base);
+        let _ = writeln!(out, "        Bp{} {{ handle: handle }}", base);
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+        let _ = writeln!(out, "impl {} for Bp{} {{", name, base);
+        for (i, method) in interface.methods.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            self.proxy_method(ctx, &mut out, method)?;
+        }
+        let _ = writeln!(out, "}}\n");
+
+        // The stub.
+        let _ = writeln!(out, "/// Exposes a local implementation of `{}` to other processes.", name);
+        let _ = writeln!(out, "pub struct Bn{}<T>(pub T);\n", base);
+        let _ = writeln!(out, "impl<T: {} + Send + Sync + 'static> Bn{}<T> {{", name, base);
+        let _ = writeln!(out, "    /// Wrap `inner` in a binder that can be sent to other processes.");
+        let _ = writeln!(out, "    pub fn new_binder(inner: T) -> ::binder::IBinder {{");
+        let _ = writeln!(out, "        ::binder::IBinder::Local(::std::sync::Arc::new(Bn{}(inner)))", base);
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+        let _ = writeln!(out, "impl<T: {} + Send + Sync> ::binder::local_binder::LocalBinder for Bn{}<T> {{", name, base);
+        let _ = writeln!(out, "    fn get_interface_descriptor(&self) -> &str {{");
+        let _ = writeln!(out, "        DESCRIPTOR");
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn on_transact(&self, code: u32, _aidl_data: &mut ::binder::parcel::Parcel, _aidl_reply: &mut ::binder::parcel::OwnedParcel, _flags: u32) -> ::binder::error::BinderResult<()> {{");
+        let _ = writeln!(out, "        match code {{");
+        for method in &interface.methods {
+            self.stub_method(ctx, &mut out, method)?;
+        }
+        let _ = writeln!(out, "            _ => Err(::binder::error::BinderErrorKind::UnknownTransaction.into())");
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}");
+        Ok(out)
+    }
+
+    /// The signature of a method of the interface trait. The proxy and stub
+    /// prefix the argument names to avoid clashing with their own variables.
+    fn signature(&self, ctx: &Context, method: &Method, prefix_args: bool) -> Result<String, Error> {
+        let mut sig = format!("{}(&self", snake_case(&method.name));
+        for arg in &method.args {
+            let ty = self.rust_type(ctx, &arg.ty)?;
+            let ty = match arg.direction {
+                // Strings are borrowed as str, like in the rest of the crate.
+                Direction::In if arg.ty.kind == TypeKind::String && arg.ty.nullable => "Option<&str>".to_owned(),
+                Direction::In if arg.ty.kind == TypeKind::String => "&str".to_owned(),
+                Direction::In if self.is_copy(ctx, &arg.ty)? => ty,
+                Direction::In => format!("&{}", ty),
+                Direction::Out | Direction::InOut => format!("&mut {}", ty)
+            };
+            if prefix_args {
+                let _ = write!(sig, ", _arg_{}: {}", arg.name, ty);
+            } else {
+                let _ = write!(sig, ", {}: {}", snake_case(&arg.name), ty);
+            }
+        }
+        let ret = match method.ret {
+            Some(ref ret) => self.rust_type(ctx, ret)?,
+            None => "()".to_owned()
+        };
+        let _ = write!(sig, ") -> ::binder::error::BinderResult<{}>", ret);
+        Ok(sig)
+    }
+
+    fn proxy_method(&self, ctx: &Context, out: &mut String, method: &Method) -> Result<(), Error> {
+        let _ = writeln!(out, "    fn {} {{", self.signature(ctx, method, true)?);
+        let _ = writeln!(out, "        let handle = self.handle.borrow();");
+        let _ = writeln!(out, "        let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());");
+        let _ = writeln!(out, "        _aidl_data.write_interface_token(DESCRIPTOR);");
+        for arg in &method.args {
+            match arg.direction {
+                Direction::In if arg.ty.kind == TypeKind::String && arg.ty.nullable => {
+                    let _ = writeln!(out, "        _aidl_data.write_nullable_string16(_arg_{});", arg.name);
+                },
+                Direction::In if arg.ty.kind == TypeKind::String => {
+                    let _ = writeln!(out, "        _aidl_data.write_string16(_arg_{});", arg.name);
+                },
+                Direction::In | Direction::InOut => {
+                    let val = if arg.direction == Direction::In && self.is_copy(ctx, &arg.ty)? {
+                        format!("&_arg_{}", arg.name)
+                    } else {
+                        format!("&*_arg_{}", arg.name)
+                    };
+                    let _ = writeln!(out, "        ::binder::parcelable::Parcelable::write_value({}, &mut _aidl_data)?;", val);
+                },
+                // The other side needs to know how big out arrays are.
+                Direction::Out if Self::is_array(&arg.ty) => {
+                    if arg.ty.nullable {
+                        let _ = writeln!(out, "        _aidl_data.write_i32(_arg_{}.as_ref().map_or(-1, |v| v.len() as i32));", arg.name);
+                    } else {
+                        let _ = writeln!(out, "        _aidl_data.write_i32(_arg_{}.len() as i32);", arg.name);
+                    }
+                },
+                Direction::Out => ()
+            }
+        }
+        if method.oneway {
+            let _ = writeln!(out, "        handle.transact_one_way(TRANSACTION_{}, &mut _aidl_data, 0)?;", method.name);
+            let _ = writeln!(out, "        Ok(())");
+            let _ = writeln!(out, "    }}");
+            return Ok(())
+        }
+        let _ = writeln!(out, "        let mut _aidl_reply = handle.transact(TRANSACTION_{}, &mut _aidl_data, 0)?;", method.name);
+        let _ = writeln!(out, "        ::binder::parcel::Parcel::read_exception(&mut _aidl_reply)?.into_result()?;");
+        if method.ret.is_some() {
+            let _ = writeln!(out, "        let _aidl_ret = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;");
+        }
+        for arg in &method.args {
+            if arg.direction != Direction::In {
+                let _ = writeln!(out, "        *_arg_{} = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;", arg.name);
+            }
+        }
+        if method.ret.is_some() {
+            let _ = writeln!(out, "        Ok(_aidl_ret)");
+        } else {
+            let _ = writeln!(out, "        Ok(())");
+        }
+        let _ = writeln!(out, "    }}");
+        Ok(())
+    }
+
+    fn stub_method(&self, ctx: &Context, out: &mut String, method: &Method) -> Result<(), Error> {
+        let _ = writeln!(out, "            TRANSACTION_{} => {{", method.name);
+        let _ = writeln!(out, "                ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;");
+        let mut call_args = Vec::new();
+        for arg in &method.args {
+            let ty = self.rust_type(ctx, &arg.ty)?;
+            match arg.direction {
+                Direction::In | Direction::InOut => {
+                    let _ = writeln!(out, "                let mut _arg_{} : {} = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;", arg.name, ty);
+                },
+                // We allocate out arrays ourselves, the caller only sends
+                // their length, -1 standing for null.
+                Direction::Out if Self::is_array(&arg.ty) => {
+                    let _ = writeln!(out, "                let _aidl_len = ::binder::parcel::Parcel::read_i32(&mut *_aidl_data)?;");
+                    if !arg.ty.nullable {
+                        let _ = writeln!(out, "                if _aidl_len == -1 {{");
+                        let _ = writeln!(out, "                    return Err(::binder::error::BinderErrorKind::UnexpectedNull.into())");
+                        let _ = writeln!(out, "                }}");
+                    }
+                    let _ = writeln!(out, "                if _aidl_len < -1 || _aidl_len as i64 > ::binder::parcelable::MAX_OUT_ARRAY_LEN as i64 {{");
+                    let _ = writeln!(out, "                    return Err(::binder::error::BinderErrorKind::BadValue.into())");
+                    let _ = writeln!(out, "                }}");
+                    let make = "(0.._aidl_len).map(|_| ::std::default::Default::default()).collect()";
+                    if arg.ty.nullable {
+                        let _ = writeln!(out, "                let mut _arg_{} : {} = if _aidl_len == -1 {{ None }} else {{ Some({}) }};", arg.name, ty, make);
+                    } else {
+                        let _ = writeln!(out, "                let mut _arg_{} : {} = {};", arg.name, ty, make);
+                    }
+                },
+                Direction::Out => {
+                    let _ = writeln!(out, "                let mut _arg_{} : {} = ::std::default::Default::default();", arg.name, ty);
+                }
+            }
+            call_args.push(match arg.direction {
+                Direction::In if arg.ty.kind == TypeKind::String && arg.ty.nullable => format!("_arg_{}.as_ref().map(|s| &s[..])", arg.name),
+                Direction::In if self.is_copy(ctx, &arg.ty)? => format!("_arg_{}", arg.name),
+                Direction::In => format!("&_arg_{}", arg.name),
+                Direction::Out | Direction::InOut => format!("&mut _arg_{}", arg.name)
+            });
+        }
+        let call = format!("self.0.{}({})", snake_case(&method.name), call_args.join(", "));
+        if method.oneway {
+            let _ = writeln!(out, "                let _aidl_ret = {}?;", call);
+        } else {
+            // Exceptions are sent back to the caller, other errors become the
+            // status code of the reply.
+            let _ = writeln!(out, "                let _aidl_ret = match {} {{", call);
+            let _ = writeln!(out, "                    Ok(ret) => ret,");
+            let _ = writeln!(out, "                    Err(::binder::error::BinderError(::binder::error::BinderErrorKind::ServiceException(status), _)) => {{");
+            let _ = writeln!(out, "                        _aidl_reply.write_exception(&status);");
+            let _ = writeln!(out, "                        return Ok(())");
+            let _ = writeln!(out, "                    }},");
+            let _ = writeln!(out, "                    Err(err) => return Err(err)");
+            let _ = writeln!(out, "                }};");
+            let _ = writeln!(out, "                _aidl_reply.write_no_exception();");
+            if method.ret.is_some() {
+                let _ = writeln!(out, "                ::binder::parcelable::Parcelable::write_value(&_aidl_ret, _aidl_reply)?;");
+            }
+            for arg in &method.args {
+                if arg.direction != Direction::In {
+                    let _ = writeln!(out, "                ::binder::parcelable::Parcelable::write_value(&_arg_{}, _aidl_reply)?;", arg.name);
+                }
+            }
+        }
+        if method.ret.is_none() {
+            let _ = writeln!(out, "                let () = _aidl_ret;");
+        }
+        let _ = writeln!(out, "                Ok(())");
+        let _ = writeln!(out, "            }},");
+        Ok(())
+    }
+
+    fn parcelable(&self, ctx: &Context, parcelable: &Parcelable) -> Result<String, Error> {
+        let name = &parcelable.name;
+        let fields = parcelable.fields.as_ref().expect("Only structured parcelables are generated");
+        let mut out = String::new();
+
+        let _ = writeln!(out, "#[derive(Debug, Clone, PartialEq)]");
+        let _ = writeln!(out, "pub struct {} {{", name);
+        for (i, field) in fields.iter().enumerate() {
+            let sep = if i + 1 < fields.len() { "," } else { "" };
+            let _ = writeln!(out, "    pub {}: {}{}", escape(&field.name), self.field_type(ctx, &field.ty)?, sep);
+        }
+        let _ = writeln!(out, "}}\n");
+
+        // Fields the writer didn't know about keep their default value.
+        let _ = writeln!(out, "impl Default for {} {{", name);
+        let _ = writeln!(out, "    fn default() -> {} {{", name);
+        let _ = writeln!(out, "        {} {{", name);
+        for (i, field) in fields.iter().enumerate() {
+            let sep = if i + 1 < fields.len() { "," } else { "" };
+            let value = match field.default {
+                Some(ref value) => {
+                    let mut inner_ty = field.ty.clone();
+                    inner_ty.nullable = false;
+                    let mut value = self.value(ctx, &inner_ty, value)?;
+                    if inner_ty.kind == TypeKind::String {
+                        value = format!("{}.to_owned()", value);
+                    }
+                    if field.ty.nullable {
+                        value = format!("Some({})", value);
+                    }
+                    value
+                },
+                None => "::std::default::Default::default()".to_owned()
+            };
+            let _ = writeln!(out, "            {}: {}{}", escape(&field.name), value, sep);
+        }
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+
+        let _ = writeln!(out, "impl ::binder::parcelable::Parcelable for {} {{", name);
+        let _ = writeln!(out, "    fn write_to_parcel(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {{");
+        let _ = writeln!(out, "        ::binder::parcelable::write_sized(parcel, |parcel| {{");
+        for field in fields {
+            let _ = writeln!(out, "            ::binder::parcelable::Parcelable::write_value(&self.{}, parcel)?;", escape(&field.name));
+        }
+        let _ = writeln!(out, "            Ok(())");
+        let _ = writeln!(out, "        }})");
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn read_from_parcel<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {{");
+        let _ = writeln!(out, "        let end = ::binder::parcelable::read_size_header(parcel)?;");
+        let _ = writeln!(out, "        let mut result = {}::default();", name);
+        for field in fields {
+            let _ = writeln!(out, "        if ::binder::parcel::Parcel::data_position(parcel) < end {{");
+            let _ = writeln!(out, "            result.{} = ::binder::parcelable::Parcelable::read_value(parcel)?;", escape(&field.name));
+            let _ = writeln!(out, "        }}");
+        }
+        let _ = writeln!(out, "        // Skip the fields we don't know about.");
+        let _ = writeln!(out, "        ::binder::parcel::Parcel::set_data_position(parcel, end)?;");
+        let _ = writeln!(out, "        Ok(result)");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}");
+        Ok(out)
+    }
+
+    fn enumeration(&self, enumeration: &Enum) -> String {
+        let name = &enumeration.name;
+        let backing = primitive_type(enumeration.backing);
+        let (write, read) = match enumeration.backing {
+            Primitive::Byte => ("write_i8", "read_i8"),
+            Primitive::Int => ("write_i32", "read_i32"),
+            _ => ("write_i64", "read_i64")
+        };
+        let mut out = String::new();
+
+        let _ = writeln!(out, "#[repr({})]", backing);
+        let _ = writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]");
+        let _ = writeln!(out, "pub enum {} {{", name);
+        for (i, &(ref variant, value)) in enumeration.enumerators.iter().enumerate() {
+            let sep = if i + 1 < enumeration.enumerators.len() { "," } else { "" };
+            let _ = writeln!(out, "    {} = {}{}", variant, value, sep);
+        }
+        let _ = writeln!(out, "}}\n");
+
+        let _ = writeln!(out, "impl {} {{", name);
+        let _ = writeln!(out, "    fn from_value(val: {}) -> ::binder::error::BinderResult<{}> {{", backing, name);
+        let _ = writeln!(out, "        match val {{");
+        for &(ref variant, value) in &enumeration.enumerators {
+            let _ = writeln!(out, "            {} => Ok({}::{}),", value, name, variant);
+        }
+        let _ = writeln!(out, "            _ => Err(::binder::error::BinderErrorKind::BadValue.into())");
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+
+        if let Some((first, _)) = enumeration.enumerators.first() {
+            let _ = writeln!(out, "impl Default for {} {{", name);
+            let _ = writeln!(out, "    fn default() -> {} {{", name);
+            let _ = writeln!(out, "        {}::{}", name, first);
+            let _ = writeln!(out, "    }}");
+            let _ = writeln!(out, "}}\n");
+        }
+
+        // Enums are sent like the integer backing them, without a marker.
+        let _ = writeln!(out, "impl ::binder::parcelable::Parcelable for {} {{", name);
+        let _ = writeln!(out, "    fn write_to_parcel(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {{");
+        let _ = writeln!(out, "        parcel.{}(*self as {});", write, backing);
+        let _ = writeln!(out, "        Ok(())");
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn read_from_parcel<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {{");
+        let _ = writeln!(out, "        {}::from_value(::binder::parcel::Parcel::{}(parcel)?)", name, read);
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn write_value(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {{");
+        let _ = writeln!(out, "        self.write_to_parcel(parcel)");
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn read_value<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {{");
+        let _ = writeln!(out, "        Self::read_from_parcel(parcel)");
+        let _ = writeln!(out, "    }}");
+        if enumeration.backing == Primitive::Byte {
+            // Arrays of byte enums are packed, like byte arrays.
+            let _ = writeln!(out, "\n    fn write_slice(val: Option<&[Self]>, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {{");
+            let _ = writeln!(out, "        let bytes = val.map(|val| val.iter().map(|e| *e as i8 as u8).collect::<Vec<u8>>());");
+            let _ = writeln!(out, "        parcel.write_nullable_byte_vector(bytes.as_ref().map(|b| &b[..]));");
+            let _ = writeln!(out, "        Ok(())");
+            let _ = writeln!(out, "    }}\n");
+            let _ = writeln!(out, "    fn read_vec<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Option<Vec<Self>>> {{");
+            let _ = writeln!(out, "        match ::binder::parcel::ParcelVectorExt::read_nullable_byte_vector(parcel)? {{");
+            let _ = writeln!(out, "            Some(bytes) => bytes.into_iter().map(|b| {}::from_value(b as i8)).collect::<::binder::error::BinderResult<Vec<Self>>>().map(Some),", name);
+            let _ = writeln!(out, "            None => Ok(None)");
+            let _ = writeln!(out, "        }}");
+            let _ = writeln!(out, "    }}");
+        }
+        let _ = writeln!(out, "}}");
+        out
+    }
+}
@@ -0,0 +1,210 @@
+//!
+//! Binder AIDL
+//!
+//! Generates rust code from AIDL files, to be used from a build script. Each
+//! interface gets a trait, a proxy (`BpFoo` for `IFoo`) implementing it over a
+//! remote `Handle`, and a stub (`BnFoo`) exposing a local implementation of it
+//! as a `LocalBinder`. Structured parcelables and enums get a rust type
+//! implementing `Parcelable`.
+//!
+//! In `build.rs` :
+//!
+//! ```no_run
+//! extern crate binder_aidl;
+//!
+//! fn main() {
+//!     binder_aidl::Builder::new()
+//!         .source("aidl")
+//!         .generate()
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! And in the crate, which needs to depend on `binder` :
+//!
+//! ```ignore
+//! extern crate binder;
+//!
+//! include!(concat!(env!("OUT_DIR"), "/aidl.rs"));
+//!
+//! use android::os::IFoo::{IFoo, BpFoo, BnFoo};
+//! ```
+//!
+//! Methods are renamed to snake_case. Arguments are passed by value for
+//! primitives and enums, as `&str` for strings, by reference otherwise, and by
+//! mutable reference for `out` and `inout` arguments. Interfaces are sent as plain `IBinder`s.
+//! Binder fields of parcelables are always `Option`s, as they have no default.
+//! Parcelables that are only declared (`parcelable Foo;`) have to be mapped to
+//! a hand-written type with `Builder::map_type`.
+//!
+//! Exceptions thrown by a remote service are returned as a `ServiceException`
+//! error. Likewise, a local implementation returning a `ServiceException`
+//! sends it back to the caller as an exception.
+//!
+
+// Like the rest of the workspace, struct fields are always spelled out.
+#![allow(clippy::redundant_field_names)]
+
+pub mod ast;
+mod parser;
+mod gen;
+
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub use parser::parse;
+
+/// An error that occured while generating the code.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse {
+        /// The file being parsed, if it came from a file.
+        file: Option<PathBuf>,
+        line: usize,
+        message: String
+    },
+    Generate(String)
+}
+
+impl Error {
+    fn parse(line: usize, message: &str) -> Error {
+        Error::Parse {
+            file: None,
+            line: line,
+            message: message.to_owned()
+        }
+    }
+
+    fn generate(message: String) -> Error {
+        Error::Generate(message)
+    }
+
+    fn in_file(self, path: &Path) -> Error {
+        match self {
+            Error::Parse { line, message, .. } => Error::Parse {
+                file: Some(path.to_owned()),
+                line: line,
+                message: message
+            },
+            err => err
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::Parse { file: Some(ref file), line, ref message } => write!(f, "{}:{}: {}", file.display(), line, message),
+            Error::Parse { file: None, line, ref message } => write!(f, "line {}: {}", line, message),
+            Error::Generate(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+/// Configures the code generation.
+#[derive(Debug, Default)]
+pub struct Builder {
+    sources: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    type_map: HashMap<Vec<String>, String>
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Add an AIDL file, or a directory that will be searched recursively for
+    /// `.aidl` files.
+    pub fn source<P: AsRef<Path>>(mut self, path: P) -> Builder {
+        self.sources.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Use the hand-written type at `rust_path` for the parcelable
+    /// `aidl_name`, e.g. `map_type("android.os.Bundle", "::bundle::Bundle")`.
+    pub fn map_type(mut self, aidl_name: &str, rust_path: &str) -> Builder {
+        let name = aidl_name.split('.').map(|s| s.to_owned()).collect();
+        self.type_map.insert(name, rust_path.to_owned());
+        self
+    }
+
+    /// Where to write the generated code. Defaults to `$OUT_DIR/aidl.rs`.
+    pub fn output<P: AsRef<Path>>(mut self, path: P) -> Builder {
+        self.output = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Parse the sources and generate the code, returning it.
+    pub fn generate_string(&self) -> Result<String, Error> {
+        let mut files = Vec::new();
+        for source in &self.sources {
+            find_aidl_files(source, &mut files)?;
+        }
+        let mut documents = Vec::new();
+        for file in &files {
+            let mut source = String::new();
+            fs::File::open(file)?.read_to_string(&mut source)?;
+            documents.push(parse(&source).map_err(|err| err.in_file(file))?);
+        }
+        gen::Generator::new(&documents, &self.type_map)?.generate()
+    }
+
+    /// Parse the sources and write the generated code to the output file.
+    ///
+    /// Cargo is told to rerun the build script whenever the sources change.
+    pub fn generate(self) -> Result<(), Error> {
+        for source in &self.sources {
+            println!("cargo:rerun-if-changed={}", source.display());
+        }
+        let code = self.generate_string()?;
+        let output = match self.output {
+            Some(ref output) => output.clone(),
+            None => match env::var_os("OUT_DIR") {
+                Some(dir) => Path::new(&dir).join("aidl.rs"),
+                None => return Err(Error::generate("OUT_DIR isn't set, use Builder::output".to_owned()))
+            }
+        };
+        fs::File::create(output)?.write_all(code.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn find_aidl_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        // Keep the output stable.
+        entries.sort_by_key(|entry| entry.path());
+        for entry in entries {
+            let path = entry.path();
+            if path.is_dir() || path.extension().is_some_and(|ext| ext == "aidl") {
+                find_aidl_files(&path, files)?;
+            }
+        }
+    } else {
+        files.push(path.to_owned());
+    }
+    Ok(())
+}
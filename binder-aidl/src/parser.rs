@@ -0,0 +1,536 @@
+//!
+//! A parser for the subset of the AIDL language we know how to generate code
+//! for.
+//!
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use ast::*;
+use Error;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Char(char),
+    Punct(char)
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            chars: source.chars().peekable(),
+            line: 1
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Error> {
+        loop {
+            match self.chars.peek().cloned() {
+                Some(c) if c.is_whitespace() => { self.bump(); },
+                Some('/') => {
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    match lookahead.next() {
+                        Some('/') => {
+                            while let Some(c) = self.bump() {
+                                if c == '\n' {
+                                    break;
+                                }
+                            }
+                        },
+                        Some('*') => {
+                            self.bump();
+                            self.bump();
+                            let mut prev = ' ';
+                            loop {
+                                match self.bump() {
+                                    Some('/') if prev == '*' => break,
+                                    Some(c) => prev = c,
+                                    None => return Err(Error::parse(self.line, "unterminated comment"))
+                                }
+                            }
+                        },
+                        _ => return Ok(())
+                    }
+                },
+                _ => return Ok(())
+            }
+        }
+    }
+
+    /// Lex the whole source, returning the tokens along with their line.
+    fn tokenize(mut self) -> Result<Vec<(Token, usize)>, Error> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_whitespace_and_comments()?;
+            let line = self.line;
+            let c = match self.bump() {
+                Some(c) => c,
+                None => return Ok(tokens)
+            };
+            let token = if c.is_alphabetic() || c == '_' {
+                let mut ident = c.to_string();
+                while let Some(c) = self.chars.peek().cloned() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    self.bump();
+                }
+                Token::Ident(ident)
+            } else if c.is_ascii_digit() {
+                let mut number = c.to_string();
+                while let Some(c) = self.chars.peek().cloned() {
+                    if !(c.is_alphanumeric() || c == '.') {
+                        break;
+                    }
+                    number.push(c);
+                    self.bump();
+                }
+                Token::Number(number)
+            } else if c == '"' {
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => break,
+                        Some('\\') => match self.bump() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(c),
+                            None => return Err(Error::parse(line, "unterminated string"))
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err(Error::parse(line, "unterminated string"))
+                    }
+                }
+                Token::Str(s)
+            } else if c == '\'' {
+                let c = match self.bump() {
+                    Some('\\') => self.bump(),
+                    c => c
+                };
+                match (c, self.bump()) {
+                    (Some(c), Some('\'')) => Token::Char(c),
+                    _ => return Err(Error::parse(line, "invalid character literal"))
+                }
+            } else {
+                Token::Punct(c)
+            };
+            tokens.push((token, line));
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize
+}
+
+/// The annotations we care about.
+#[derive(Default)]
+struct Annotations {
+    nullable: bool,
+    backing: Option<String>
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some(&(_, line)) => line,
+            None => self.tokens.last().map(|&(_, line)| line).unwrap_or(1)
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, Error> {
+        match self.tokens.get(self.pos).cloned() {
+            Some((token, _)) => {
+                self.pos += 1;
+                Ok(token)
+            },
+            None => Err(Error::parse(self.line(), "unexpected end of file"))
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, Error> {
+        Err(Error::parse(self.line(), msg))
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(ident)) => ident == keyword,
+            _ => false
+        }
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        if self.is_punct(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), Error> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", c))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, Error> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            _ => {
+                self.pos -= 1;
+                self.error("expected an identifier")
+            }
+        }
+    }
+
+    fn qualified_name(&mut self) -> Result<Vec<String>, Error> {
+        let mut name = vec![self.ident()?];
+        while self.eat_punct('.') {
+            name.push(self.ident()?);
+        }
+        Ok(name)
+    }
+
+    fn document(&mut self) -> Result<Document, Error> {
+        let mut package = Vec::new();
+        let mut imports = Vec::new();
+        let mut definitions = Vec::new();
+
+        if self.eat_keyword("package") {
+            package = self.qualified_name()?;
+            self.expect_punct(';')?;
+        }
+        while self.eat_keyword("import") {
+            imports.push(self.qualified_name()?);
+            self.expect_punct(';')?;
+        }
+        while self.peek().is_some() {
+            definitions.push(self.definition()?);
+        }
+        Ok(Document {
+            package: package,
+            imports: imports,
+            definitions: definitions
+        })
+    }
+
+    fn annotations(&mut self) -> Result<Annotations, Error> {
+        let mut annotations = Annotations::default();
+        while self.eat_punct('@') {
+            let name = self.ident()?;
+            let mut params = Vec::new();
+            if self.eat_punct('(') {
+                while !self.eat_punct(')') {
+                    let key = self.ident()?;
+                    self.expect_punct('=')?;
+                    params.push((key, self.value()?));
+                    self.eat_punct(',');
+                }
+            }
+            match &*name {
+                "nullable" => annotations.nullable = true,
+                "Backing" => for (key, value) in params {
+                    match (&*key, value) {
+                        ("type", Value::String(ty)) => annotations.backing = Some(ty),
+                        _ => return self.error("invalid @Backing annotation")
+                    }
+                },
+                // The others only matter to other backends.
+                _ => ()
+            }
+        }
+        Ok(annotations)
+    }
+
+    fn definition(&mut self) -> Result<Definition, Error> {
+        let annotations = self.annotations()?;
+        let oneway = self.eat_keyword("oneway");
+        if self.eat_keyword("interface") {
+            self.interface(oneway).map(Definition::Interface)
+        } else if oneway {
+            self.error("only interfaces can be oneway")
+        } else if self.eat_keyword("parcelable") {
+            self.parcelable().map(Definition::Parcelable)
+        } else if self.eat_keyword("enum") {
+            self.enumeration(annotations).map(Definition::Enum)
+        } else {
+            self.error("expected interface, parcelable or enum")
+        }
+    }
+
+    fn interface(&mut self, oneway: bool) -> Result<Interface, Error> {
+        let name = self.ident()?;
+        let mut constants = Vec::new();
+        let mut methods = Vec::new();
+        self.expect_punct('{')?;
+        while !self.eat_punct('}') {
+            if self.eat_keyword("const") {
+                let ty = self.ty()?;
+                let name = self.ident()?;
+                self.expect_punct('=')?;
+                let value = self.value()?;
+                self.expect_punct(';')?;
+                constants.push(Constant {
+                    ty: ty,
+                    name: name,
+                    value: value
+                });
+            } else {
+                methods.push(self.method(oneway)?);
+            }
+        }
+        Ok(Interface {
+            name: name,
+            oneway: oneway,
+            constants: constants,
+            methods: methods
+        })
+    }
+
+    fn method(&mut self, interface_oneway: bool) -> Result<Method, Error> {
+        // Annotations on the method apply to its return type.
+        let annotations = self.annotations()?;
+        let oneway = self.eat_keyword("oneway") || interface_oneway;
+        let ret = if self.eat_keyword("void") {
+            None
+        } else {
+            let mut ty = self.ty()?;
+            ty.nullable |= annotations.nullable;
+            Some(ty)
+        };
+        if oneway && ret.is_some() {
+            return self.error("oneway methods can't return anything")
+        }
+        let name = self.ident()?;
+        let mut args = Vec::new();
+        self.expect_punct('(')?;
+        while !self.eat_punct(')') {
+            let annotations = self.annotations()?;
+            let direction = if self.eat_keyword("in") {
+                Direction::In
+            } else if self.eat_keyword("out") {
+                Direction::Out
+            } else if self.eat_keyword("inout") {
+                Direction::InOut
+            } else {
+                Direction::In
+            };
+            let mut ty = self.ty()?;
+            ty.nullable |= annotations.nullable;
+            if oneway && direction != Direction::In {
+                return self.error("oneway methods can only have in arguments")
+            }
+            args.push(Arg {
+                direction: direction,
+                ty: ty,
+                name: self.ident()?
+            });
+            if !self.eat_punct(',') && !self.is_punct(')') {
+                return self.error("expected `,` or `)`")
+            }
+        }
+        let id = if self.eat_punct('=') {
+            match self.value()? {
+                Value::Int(id) if id >= 0 => Some(id as u32),
+                _ => return self.error("invalid transaction id")
+            }
+        } else {
+            None
+        };
+        self.expect_punct(';')?;
+        Ok(Method {
+            oneway: oneway,
+            ret: ret,
+            name: name,
+            args: args,
+            id: id
+        })
+    }
+
+    fn parcelable(&mut self) -> Result<Parcelable, Error> {
+        let name = self.ident()?;
+        if !self.eat_punct('{') {
+            // A parcelable implemented by hand. There might be extra
+            // information for other backends, like cpp_header.
+            while !self.eat_punct(';') {
+                self.next()?;
+            }
+            return Ok(Parcelable {
+                name: name,
+                fields: None
+            })
+        }
+        let mut fields = Vec::new();
+        while !self.eat_punct('}') {
+            let ty = self.ty()?;
+            let name = self.ident()?;
+            let default = if self.eat_punct('=') {
+                Some(self.value()?)
+            } else {
+                None
+            };
+            self.expect_punct(';')?;
+            fields.push(Field {
+                ty: ty,
+                name: name,
+                default: default
+            });
+        }
+        Ok(Parcelable {
+            name: name,
+            fields: Some(fields)
+        })
+    }
+
+    fn enumeration(&mut self, annotations: Annotations) -> Result<Enum, Error> {
+        let name = self.ident()?;
+        let backing = match annotations.backing.as_deref() {
+            None | Some("byte") => Primitive::Byte,
+            Some("int") => Primitive::Int,
+            Some("long") => Primitive::Long,
+            Some(_) => return self.error("enums can only be backed by byte, int or long")
+        };
+        let mut enumerators = Vec::new();
+        let mut next = 0;
+        self.expect_punct('{')?;
+        while !self.eat_punct('}') {
+            let name = self.ident()?;
+            if self.eat_punct('=') {
+                match self.value()? {
+                    Value::Int(val) => next = val,
+                    _ => return self.error("enumerators must be integers")
+                }
+            }
+            enumerators.push((name, next));
+            next += 1;
+            if !self.eat_punct(',') && !self.is_punct('}') {
+                return self.error("expected `,` or `}`")
+            }
+        }
+        Ok(Enum {
+            name: name,
+            backing: backing,
+            enumerators: enumerators
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type, Error> {
+        let annotations = self.annotations()?;
+        let name = self.qualified_name()?;
+        let kind = if name.len() == 1 {
+            match &*name[0] {
+                "boolean" => TypeKind::Primitive(Primitive::Boolean),
+                "byte" => TypeKind::Primitive(Primitive::Byte),
+                "char" => TypeKind::Primitive(Primitive::Char),
+                "int" => TypeKind::Primitive(Primitive::Int),
+                "long" => TypeKind::Primitive(Primitive::Long),
+                "float" => TypeKind::Primitive(Primitive::Float),
+                "double" => TypeKind::Primitive(Primitive::Double),
+                "String" | "CharSequence" => TypeKind::String,
+                "IBinder" => TypeKind::IBinder,
+                "List" => {
+                    self.expect_punct('<')?;
+                    let inner = self.ty()?;
+                    self.expect_punct('>')?;
+                    TypeKind::Array(Box::new(inner))
+                },
+                "Map" | "FileDescriptor" | "ParcelFileDescriptor" =>
+                    return self.error(&format!("{} is not supported", name[0])),
+                _ => TypeKind::Named(name)
+            }
+        } else {
+            TypeKind::Named(name)
+        };
+        let mut ty = Type {
+            kind: kind,
+            nullable: false
+        };
+        while self.eat_punct('[') {
+            self.expect_punct(']')?;
+            ty = Type {
+                kind: TypeKind::Array(Box::new(ty)),
+                nullable: false
+            };
+        }
+        ty.nullable = annotations.nullable;
+        Ok(ty)
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        let negative = self.eat_punct('-');
+        let value = match self.next()? {
+            Token::Number(number) => parse_number(&number, negative),
+            Token::Str(ref s) if !negative => Some(Value::String(s.clone())),
+            Token::Char(c) if !negative => Some(Value::Char(c)),
+            Token::Ident(ref ident) if ident == "true" && !negative => Some(Value::Bool(true)),
+            Token::Ident(ref ident) if ident == "false" && !negative => Some(Value::Bool(false)),
+            _ => None
+        };
+        match value {
+            Some(value) => Ok(value),
+            None => {
+                self.pos -= 1;
+                self.error("expected a literal value")
+            }
+        }
+    }
+}
+
+fn parse_number(number: &str, negative: bool) -> Option<Value> {
+    let number = number.trim_end_matches(['l', 'L']);
+    let sign = if negative { -1 } else { 1 };
+    if number.starts_with("0x") || number.starts_with("0X") {
+        // Hex literals are allowed to overflow, like in java.
+        u64::from_str_radix(&number[2..], 16).ok().map(|n| Value::Int(sign * n as i64))
+    } else if number.contains('.') || number.ends_with('f') || number.ends_with('d') {
+        let number = number.trim_end_matches(['f', 'd']);
+        number.parse::<f64>().ok().map(|n| Value::Float(sign as f64 * n))
+    } else {
+        number.parse::<i64>().ok().map(|n| Value::Int(sign * n))
+    }
+}
+
+/// Parse the contents of an AIDL file.
+pub fn parse(source: &str) -> Result<Document, Error> {
+    let tokens = Lexer::new(source).tokenize()?;
+    Parser { tokens: tokens, pos: 0 }.document()
+}
@@ -0,0 +1,13 @@
+extern crate binder_aidl;
+
+use std::path::Path;
+
+#[test]
+fn generate_interface() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/generate");
+    let code = binder_aidl::Builder::new()
+        .source(&dir)
+        .generate_string()
+        .unwrap();
+    assert_eq!(code, include_str!("generate/expected.rs"));
+}
@@ -0,0 +1,364 @@
+// Generated by binder-aidl. Do not edit.
+
+#[allow(non_snake_case)]
+pub mod com {
+    #[allow(non_snake_case)]
+    pub mod example {
+        #[allow(non_snake_case)]
+        pub mod ICallback {
+            #![allow(non_upper_case_globals, unused_mut)]
+
+            pub const DESCRIPTOR : &'static str = "com.example.ICallback";
+
+            pub const TRANSACTION_done : u32 = ::binder::FIRST_CALL_TRANSACTION + 0;
+
+            pub trait ICallback {
+                fn done(&self, code: i32, message: Option<&str>) -> ::binder::error::BinderResult<()>;
+            }
+
+            /// Proxy for a remote `ICallback`.
+            pub struct BpCallback {
+                handle: ::std::rc::Rc<::std::cell::RefCell<::binder::Handle>>
+            }
+
+            impl BpCallback {
+                pub fn as_binder(&self) -> ::binder::IBinder {
+                    ::binder::IBinder::Remote(self.handle.clone())
+                }
+            }
+
+            impl ::binder::IInterface for BpCallback {
+                fn get_interface_descriptor() -> &'static str {
+                    DESCRIPTOR
+                }
+                fn from_handle(handle: ::std::rc::Rc<::std::cell::RefCell<::binder::Handle>>) -> BpCallback {
+                    BpCallback { handle: handle }
+                }
+            }
+
+            impl ICallback for BpCallback {
+                fn done(&self, _arg_code: i32, _arg_message: Option<&str>) -> ::binder::error::BinderResult<()> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    ::binder::parcelable::Parcelable::write_value(&_arg_code, &mut _aidl_data)?;
+                    _aidl_data.write_nullable_string16(_arg_message);
+                    handle.transact_one_way(TRANSACTION_done, &mut _aidl_data, 0)?;
+                    Ok(())
+                }
+            }
+
+            /// Exposes a local implementation of `ICallback` to other processes.
+            pub struct BnCallback<T>(pub T);
+
+            impl<T: ICallback + Send + Sync + 'static> BnCallback<T> {
+                /// Wrap `inner` in a binder that can be sent to other processes.
+                pub fn new_binder(inner: T) -> ::binder::IBinder {
+                    ::binder::IBinder::Local(::std::sync::Arc::new(BnCallback(inner)))
+                }
+            }
+
+            impl<T: ICallback + Send + Sync> ::binder::local_binder::LocalBinder for BnCallback<T> {
+                fn get_interface_descriptor(&self) -> &str {
+                    DESCRIPTOR
+                }
+
+                fn on_transact(&self, code: u32, _aidl_data: &mut ::binder::parcel::Parcel, _aidl_reply: &mut ::binder::parcel::OwnedParcel, _flags: u32) -> ::binder::error::BinderResult<()> {
+                    match code {
+                        TRANSACTION_done => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let mut _arg_code : i32 = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let mut _arg_message : Option<String> = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let _aidl_ret = self.0.done(_arg_code, _arg_message.as_ref().map(|s| &s[..]))?;
+                            let () = _aidl_ret;
+                            Ok(())
+                        },
+                        _ => Err(::binder::error::BinderErrorKind::UnknownTransaction.into())
+                    }
+                }
+            }
+        }
+        #[allow(non_snake_case)]
+        pub mod ITest {
+            #![allow(non_upper_case_globals, unused_mut)]
+
+            pub const DESCRIPTOR : &'static str = "com.example.ITest";
+
+            pub const TRANSACTION_add : u32 = ::binder::FIRST_CALL_TRANSACTION + 0;
+            pub const TRANSACTION_greet : u32 = ::binder::FIRST_CALL_TRANSACTION + 1;
+            pub const TRANSACTION_fill : u32 = ::binder::FIRST_CALL_TRANSACTION + 2;
+            pub const TRANSACTION_post : u32 = ::binder::FIRST_CALL_TRANSACTION + 3;
+            pub const TRANSACTION_getCallback : u32 = ::binder::FIRST_CALL_TRANSACTION + 4;
+            pub const TRANSACTION_fillMaybe : u32 = ::binder::FIRST_CALL_TRANSACTION + 5;
+
+            pub trait ITest {
+                fn add(&self, a: i32, b: i32) -> ::binder::error::BinderResult<i32>;
+                fn greet(&self, name: &str, title: Option<&str>) -> ::binder::error::BinderResult<Option<String>>;
+                fn fill(&self, values: &mut Vec<i32>, names: &mut Vec<String>) -> ::binder::error::BinderResult<()>;
+                fn post(&self, callback: &::binder::IBinder, data: &Vec<u8>) -> ::binder::error::BinderResult<()>;
+                fn get_callback(&self, name: &str) -> ::binder::error::BinderResult<::binder::IBinder>;
+                fn fill_maybe(&self, values: &mut Option<Vec<i64>>) -> ::binder::error::BinderResult<()>;
+            }
+
+            /// Proxy for a remote `ITest`.
+            pub struct BpTest {
+                handle: ::std::rc::Rc<::std::cell::RefCell<::binder::Handle>>
+            }
+
+            impl BpTest {
+                pub fn as_binder(&self) -> ::binder::IBinder {
+                    ::binder::IBinder::Remote(self.handle.clone())
+                }
+            }
+
+            impl ::binder::IInterface for BpTest {
+                fn get_interface_descriptor() -> &'static str {
+                    DESCRIPTOR
+                }
+                fn from_handle(handle: ::std::rc::Rc<::std::cell::RefCell<::binder::Handle>>) -> BpTest {
+                    BpTest { handle: handle }
+                }
+            }
+
+            impl ITest for BpTest {
+                fn add(&self, _arg_a: i32, _arg_b: i32) -> ::binder::error::BinderResult<i32> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    ::binder::parcelable::Parcelable::write_value(&_arg_a, &mut _aidl_data)?;
+                    ::binder::parcelable::Parcelable::write_value(&_arg_b, &mut _aidl_data)?;
+                    let mut _aidl_reply = handle.transact(TRANSACTION_add, &mut _aidl_data, 0)?;
+                    ::binder::parcel::Parcel::read_exception(&mut _aidl_reply)?.into_result()?;
+                    let _aidl_ret = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;
+                    Ok(_aidl_ret)
+                }
+
+                fn greet(&self, _arg_name: &str, _arg_title: Option<&str>) -> ::binder::error::BinderResult<Option<String>> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    _aidl_data.write_string16(_arg_name);
+                    _aidl_data.write_nullable_string16(_arg_title);
+                    let mut _aidl_reply = handle.transact(TRANSACTION_greet, &mut _aidl_data, 0)?;
+                    ::binder::parcel::Parcel::read_exception(&mut _aidl_reply)?.into_result()?;
+                    let _aidl_ret = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;
+                    Ok(_aidl_ret)
+                }
+
+                fn fill(&self, _arg_values: &mut Vec<i32>, _arg_names: &mut Vec<String>) -> ::binder::error::BinderResult<()> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    _aidl_data.write_i32(_arg_values.len() as i32);
+                    ::binder::parcelable::Parcelable::write_value(&*_arg_names, &mut _aidl_data)?;
+                    let mut _aidl_reply = handle.transact(TRANSACTION_fill, &mut _aidl_data, 0)?;
+                    ::binder::parcel::Parcel::read_exception(&mut _aidl_reply)?.into_result()?;
+                    *_arg_values = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;
+                    *_arg_names = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;
+                    Ok(())
+                }
+
+                fn post(&self, _arg_callback: &::binder::IBinder, _arg_data: &Vec<u8>) -> ::binder::error::BinderResult<()> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    ::binder::parcelable::Parcelable::write_value(&*_arg_callback, &mut _aidl_data)?;
+                    ::binder::parcelable::Parcelable::write_value(&*_arg_data, &mut _aidl_data)?;
+                    handle.transact_one_way(TRANSACTION_post, &mut _aidl_data, 0)?;
+                    Ok(())
+                }
+
+                fn get_callback(&self, _arg_name: &str) -> ::binder::error::BinderResult<::binder::IBinder> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    _aidl_data.write_string16(_arg_name);
+                    let mut _aidl_reply = handle.transact(TRANSACTION_getCallback, &mut _aidl_data, 0)?;
+                    ::binder::parcel::Parcel::read_exception(&mut _aidl_reply)?.into_result()?;
+                    let _aidl_ret = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;
+                    Ok(_aidl_ret)
+                }
+
+                fn fill_maybe(&self, _arg_values: &mut Option<Vec<i64>>) -> ::binder::error::BinderResult<()> {
+                    let handle = self.handle.borrow();
+                    let mut _aidl_data = ::binder::parcel::OwnedParcel::new(handle.conn().clone());
+                    _aidl_data.write_interface_token(DESCRIPTOR);
+                    _aidl_data.write_i32(_arg_values.as_ref().map_or(-1, |v| v.len() as i32));
+                    let mut _aidl_reply = handle.transact(TRANSACTION_fillMaybe, &mut _aidl_data, 0)?;
+                    ::binder::parcel::Parcel::read_exception(&mut _aidl_reply)?.into_result()?;
+                    *_arg_values = ::binder::parcelable::Parcelable::read_value(&mut _aidl_reply)?;
+                    Ok(())
+                }
+            }
+
+            /// Exposes a local implementation of `ITest` to other processes.
+            pub struct BnTest<T>(pub T);
+
+            impl<T: ITest + Send + Sync + 'static> BnTest<T> {
+                /// Wrap `inner` in a binder that can be sent to other processes.
+                pub fn new_binder(inner: T) -> ::binder::IBinder {
+                    ::binder::IBinder::Local(::std::sync::Arc::new(BnTest(inner)))
+                }
+            }
+
+            impl<T: ITest + Send + Sync> ::binder::local_binder::LocalBinder for BnTest<T> {
+                fn get_interface_descriptor(&self) -> &str {
+                    DESCRIPTOR
+                }
+
+                fn on_transact(&self, code: u32, _aidl_data: &mut ::binder::parcel::Parcel, _aidl_reply: &mut ::binder::parcel::OwnedParcel, _flags: u32) -> ::binder::error::BinderResult<()> {
+                    match code {
+                        TRANSACTION_add => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let mut _arg_a : i32 = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let mut _arg_b : i32 = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let _aidl_ret = match self.0.add(_arg_a, _arg_b) {
+                                Ok(ret) => ret,
+                                Err(::binder::error::BinderError(::binder::error::BinderErrorKind::ServiceException(status), _)) => {
+                                    _aidl_reply.write_exception(&status);
+                                    return Ok(())
+                                },
+                                Err(err) => return Err(err)
+                            };
+                            _aidl_reply.write_no_exception();
+                            ::binder::parcelable::Parcelable::write_value(&_aidl_ret, _aidl_reply)?;
+                            Ok(())
+                        },
+                        TRANSACTION_greet => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let mut _arg_name : String = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let mut _arg_title : Option<String> = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let _aidl_ret = match self.0.greet(&_arg_name, _arg_title.as_ref().map(|s| &s[..])) {
+                                Ok(ret) => ret,
+                                Err(::binder::error::BinderError(::binder::error::BinderErrorKind::ServiceException(status), _)) => {
+                                    _aidl_reply.write_exception(&status);
+                                    return Ok(())
+                                },
+                                Err(err) => return Err(err)
+                            };
+                            _aidl_reply.write_no_exception();
+                            ::binder::parcelable::Parcelable::write_value(&_aidl_ret, _aidl_reply)?;
+                            Ok(())
+                        },
+                        TRANSACTION_fill => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let _aidl_len = ::binder::parcel::Parcel::read_i32(&mut *_aidl_data)?;
+                            if _aidl_len == -1 {
+                                return Err(::binder::error::BinderErrorKind::UnexpectedNull.into())
+                            }
+                            if _aidl_len < -1 || _aidl_len as i64 > ::binder::parcelable::MAX_OUT_ARRAY_LEN as i64 {
+                                return Err(::binder::error::BinderErrorKind::BadValue.into())
+                            }
+                            let mut _arg_values : Vec<i32> = (0.._aidl_len).map(|_| ::std::default::Default::default()).collect();
+                            let mut _arg_names : Vec<String> = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let _aidl_ret = match self.0.fill(&mut _arg_values, &mut _arg_names) {
+                                Ok(ret) => ret,
+                                Err(::binder::error::BinderError(::binder::error::BinderErrorKind::ServiceException(status), _)) => {
+                                    _aidl_reply.write_exception(&status);
+                                    return Ok(())
+                                },
+                                Err(err) => return Err(err)
+                            };
+                            _aidl_reply.write_no_exception();
+                            ::binder::parcelable::Parcelable::write_value(&_arg_values, _aidl_reply)?;
+                            ::binder::parcelable::Parcelable::write_value(&_arg_names, _aidl_reply)?;
+                            let () = _aidl_ret;
+                            Ok(())
+                        },
+                        TRANSACTION_post => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let mut _arg_callback : ::binder::IBinder = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let mut _arg_data : Vec<u8> = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let _aidl_ret = self.0.post(&_arg_callback, &_arg_data)?;
+                            let () = _aidl_ret;
+                            Ok(())
+                        },
+                        TRANSACTION_getCallback => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let mut _arg_name : String = ::binder::parcelable::Parcelable::read_value(&mut *_aidl_data)?;
+                            let _aidl_ret = match self.0.get_callback(&_arg_name) {
+                                Ok(ret) => ret,
+                                Err(::binder::error::BinderError(::binder::error::BinderErrorKind::ServiceException(status), _)) => {
+                                    _aidl_reply.write_exception(&status);
+                                    return Ok(())
+                                },
+                                Err(err) => return Err(err)
+                            };
+                            _aidl_reply.write_no_exception();
+                            ::binder::parcelable::Parcelable::write_value(&_aidl_ret, _aidl_reply)?;
+                            Ok(())
+                        },
+                        TRANSACTION_fillMaybe => {
+                            ::binder::parcel::Parcel::enforce_interface(&mut *_aidl_data, DESCRIPTOR)?;
+                            let _aidl_len = ::binder::parcel::Parcel::read_i32(&mut *_aidl_data)?;
+                            if _aidl_len < -1 || _aidl_len as i64 > ::binder::parcelable::MAX_OUT_ARRAY_LEN as i64 {
+                                return Err(::binder::error::BinderErrorKind::BadValue.into())
+                            }
+                            let mut _arg_values : Option<Vec<i64>> = if _aidl_len == -1 { None } else { Some((0.._aidl_len).map(|_| ::std::default::Default::default()).collect()) };
+                            let _aidl_ret = match self.0.fill_maybe(&mut _arg_values) {
+                                Ok(ret) => ret,
+                                Err(::binder::error::BinderError(::binder::error::BinderErrorKind::ServiceException(status), _)) => {
+                                    _aidl_reply.write_exception(&status);
+                                    return Ok(())
+                                },
+                                Err(err) => return Err(err)
+                            };
+                            _aidl_reply.write_no_exception();
+                            ::binder::parcelable::Parcelable::write_value(&_arg_values, _aidl_reply)?;
+                            let () = _aidl_ret;
+                            Ok(())
+                        },
+                        _ => Err(::binder::error::BinderErrorKind::UnknownTransaction.into())
+                    }
+                }
+            }
+        }
+        #[allow(non_snake_case)]
+        pub mod P {
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct P {
+                pub token: Option<::binder::IBinder>,
+                pub callback: Option<::binder::IBinder>,
+                pub x: i32
+            }
+
+            impl Default for P {
+                fn default() -> P {
+                    P {
+                        token: ::std::default::Default::default(),
+                        callback: ::std::default::Default::default(),
+                        x: 3i32
+                    }
+                }
+            }
+
+            impl ::binder::parcelable::Parcelable for P {
+                fn write_to_parcel(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {
+                    ::binder::parcelable::write_sized(parcel, |parcel| {
+                        ::binder::parcelable::Parcelable::write_value(&self.token, parcel)?;
+                        ::binder::parcelable::Parcelable::write_value(&self.callback, parcel)?;
+                        ::binder::parcelable::Parcelable::write_value(&self.x, parcel)?;
+                        Ok(())
+                    })
+                }
+
+                fn read_from_parcel<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {
+                    let end = ::binder::parcelable::read_size_header(parcel)?;
+                    let mut result = P::default();
+                    if ::binder::parcel::Parcel::data_position(parcel) < end {
+                        result.token = ::binder::parcelable::Parcelable::read_value(parcel)?;
+                    }
+                    if ::binder::parcel::Parcel::data_position(parcel) < end {
+                        result.callback = ::binder::parcelable::Parcelable::read_value(parcel)?;
+                    }
+                    if ::binder::parcel::Parcel::data_position(parcel) < end {
+                        result.x = ::binder::parcelable::Parcelable::read_value(parcel)?;
+                    }
+                    // Skip the fields we don't know about.
+                    ::binder::parcel::Parcel::set_data_position(parcel, end)?;
+                    Ok(result)
+                }
+            }
+        }
+    }
+}
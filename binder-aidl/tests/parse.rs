@@ -0,0 +1,37 @@
+extern crate binder_aidl;
+
+use binder_aidl::ast::*;
+
+#[test]
+fn parse_interface() {
+    let doc = binder_aidl::parse(r#"
+        package android.os;
+
+        interface IFoo {
+            const int VERSION = 3;
+            @nullable String greet(in String name);
+            oneway void ping(in IBinder who) = 10;
+            void fill(out int[] values);
+        }
+    "#).unwrap();
+
+    assert_eq!(doc.package, vec!["android", "os"]);
+    let iface = match doc.definitions[0] {
+        Definition::Interface(ref iface) => iface,
+        ref def => panic!("Expected an interface, got {:?}", def)
+    };
+    assert_eq!(iface.name, "IFoo");
+    assert_eq!(iface.constants[0].value, Value::Int(3));
+    assert_eq!(iface.methods[0].ret, Some(Type { kind: TypeKind::String, nullable: true }));
+    assert!(iface.methods[1].oneway);
+    assert_eq!(iface.methods[1].id, Some(10));
+    assert_eq!(iface.methods[2].args[0].direction, Direction::Out);
+}
+
+#[test]
+fn parse_error_has_line() {
+    match binder_aidl::parse("package a;\ninterface IFoo {\n  void foo(;\n}") {
+        Err(binder_aidl::Error::Parse { line, .. }) => assert_eq!(line, 3),
+        res => panic!("Expected a parse error, got {:?}", res)
+    }
+}
@@ -0,0 +1,159 @@
+//!
+//! Binder Derive
+//!
+//! `#[derive(Parcelable)]` for the binder crate.
+//!
+//! Structs are written as AIDL structured parcelables : a size header followed
+//! by their fields, in declaration order. Every field has to implement
+//! `Parcelable` and `Default`, the latter being used for the fields an older
+//! writer didn't send.
+//!
+//! C-like enums are written as their discriminant, as an int32. Enums with a
+//! `#[repr(i64)]` or `#[repr(u64)]` are written as an int64 instead.
+//!
+
+extern crate proc_macro;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+
+#[proc_macro_derive(Parcelable)]
+pub fn derive_parcelable(input: TokenStream) -> TokenStream {
+    let source = input.to_string();
+    let ast = syn::parse_derive_input(&source).unwrap();
+    let gen = match ast.body {
+        syn::Body::Struct(ref data) => impl_struct(&ast, data),
+        syn::Body::Enum(ref variants) => impl_enum(&ast, variants)
+    };
+    gen.parse().unwrap()
+}
+
+fn impl_struct(ast: &syn::DeriveInput, data: &syn::VariantData) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let fields = data.fields();
+
+    let writes : Vec<_> = fields.iter().enumerate().map(|(i, field)| {
+        let access = match field.ident {
+            Some(ref ident) => ident.clone(),
+            None => syn::Ident::new(i.to_string())
+        };
+        quote! {
+            ::binder::parcelable::Parcelable::write_value(&self.#access, parcel)?;
+        }
+    }).collect();
+
+    let bindings : Vec<_> = (0..fields.len()).map(|i| syn::Ident::new(format!("field{}", i))).collect();
+
+    // Fields the writer didn't know about get their default value.
+    let reads : Vec<_> = bindings.iter().map(|binding| quote! {
+        let #binding = if ::binder::parcel::Parcel::data_position(parcel) < end {
+            ::binder::parcelable::Parcelable::read_value(parcel)?
+        } else {
+            ::std::default::Default::default()
+        };
+    }).collect();
+
+    let construct = match *data {
+        syn::VariantData::Struct(ref fields) => {
+            let inits : Vec<_> = fields.iter().zip(bindings.iter()).map(|(field, binding)| {
+                let ident = field.ident.as_ref().unwrap();
+                quote! { #ident: #binding }
+            }).collect();
+            quote! { #name { #(#inits),* } }
+        },
+        syn::VariantData::Tuple(_) => quote! { #name(#(#bindings),*) },
+        syn::VariantData::Unit => quote! { #name }
+    };
+
+    quote! {
+        impl #impl_generics ::binder::parcelable::Parcelable for #name #ty_generics #where_clause {
+            fn write_to_parcel(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {
+                ::binder::parcelable::write_sized(parcel, |parcel| {
+                    #(#writes)*
+                    Ok(())
+                })
+            }
+
+            fn read_from_parcel<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {
+                let end = ::binder::parcelable::read_size_header(parcel)?;
+                #(#reads)*
+                // Skip the fields we don't know about.
+                ::binder::parcel::Parcel::set_data_position(parcel, end)?;
+                Ok(#construct)
+            }
+        }
+    }
+}
+
+fn impl_enum(ast: &syn::DeriveInput, variants: &[syn::Variant]) -> quote::Tokens {
+    let name = &ast.ident;
+
+    for variant in variants {
+        if variant.data != syn::VariantData::Unit {
+            panic!("#[derive(Parcelable)] only supports enums without fields");
+        }
+    }
+
+    let (ty, write, read) = if is_64bit_repr(&ast.attrs) {
+        (syn::Ident::new("i64"), syn::Ident::new("write_i64"), syn::Ident::new("read_i64"))
+    } else {
+        (syn::Ident::new("i32"), syn::Ident::new("write_i32"), syn::Ident::new("read_i32"))
+    };
+
+    let to_values : Vec<_> = variants.iter().map(|variant| {
+        let ident = &variant.ident;
+        quote! { #name::#ident => #name::#ident as #ty }
+    }).collect();
+
+    let from_values : Vec<_> = variants.iter().map(|variant| {
+        let ident = &variant.ident;
+        quote! {
+            if val == #name::#ident as #ty {
+                return Ok(#name::#ident)
+            }
+        }
+    }).collect();
+
+    quote! {
+        impl ::binder::parcelable::Parcelable for #name {
+            fn write_to_parcel(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {
+                let val = match *self {
+                    #(#to_values,)*
+                };
+                parcel.#write(val);
+                Ok(())
+            }
+
+            fn read_from_parcel<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {
+                let val = ::binder::parcel::Parcel::#read(parcel)?;
+                #(#from_values)*
+                Err(::binder::error::BinderErrorKind::BadValue.into())
+            }
+
+            // Enums are sent like the integer backing them, without a marker.
+            fn write_value(&self, parcel: &mut ::binder::parcel::OwnedParcel) -> ::binder::error::BinderResult<()> {
+                self.write_to_parcel(parcel)
+            }
+
+            fn read_value<__P: ::binder::parcel::Parcel + ?Sized>(parcel: &mut __P) -> ::binder::error::BinderResult<Self> {
+                Self::read_from_parcel(parcel)
+            }
+        }
+    }
+}
+
+/// Whether the enum has a `#[repr(i64)]` or `#[repr(u64)]` attribute.
+fn is_64bit_repr(attrs: &[syn::Attribute]) -> bool {
+    attrs.iter().any(|attr| match attr.value {
+        syn::MetaItem::List(ref ident, ref items) if ident == "repr" => {
+            items.iter().any(|item| match *item {
+                syn::NestedMetaItem::MetaItem(syn::MetaItem::Word(ref word)) => word == "i64" || word == "u64",
+                _ => false
+            })
+        },
+        _ => false
+    })
+}
@@ -0,0 +1,118 @@
+extern crate binder;
+#[macro_use]
+extern crate binder_derive;
+
+use binder::BinderConnection;
+use binder::parcel::{Parcel, OwnedParcel};
+use binder::parcelable::Parcelable;
+
+#[derive(Parcelable, Debug, Default, PartialEq)]
+struct Point {
+    x: i32,
+    y: i32
+}
+
+#[derive(Parcelable, Debug, Default, PartialEq)]
+struct Shape {
+    name: String,
+    origin: Point,
+    sides: i64
+}
+
+#[derive(Parcelable, Debug, Clone, Copy, PartialEq)]
+enum Color {
+    Red = 1,
+    Green = 2
+}
+
+#[derive(Parcelable, Debug, Clone, Copy, PartialEq)]
+#[repr(i64)]
+enum Big {
+    Huge = 1 << 40
+}
+
+fn parcel() -> OwnedParcel {
+    OwnedParcel::new(BinderConnection::open().unwrap())
+}
+
+#[test]
+fn derive_struct() {
+    let shape = Shape {
+        name: "square".to_string(),
+        origin: Point { x: 3, y: -4 },
+        sides: 4
+    };
+    let mut parcel = parcel();
+    shape.write_value(&mut parcel).unwrap();
+    let len = parcel.len();
+
+    parcel.set_position(0);
+    // Non-null marker, then the size of the fields, header included.
+    assert_eq!(parcel.read_i32().unwrap(), 1);
+    assert_eq!(parcel.read_i32().unwrap() as usize, len - 4);
+    assert_eq!(parcel.read_string16().unwrap(), "square");
+    // Nested parcelables have their own marker and header.
+    assert_eq!(parcel.read_i32().unwrap(), 1);
+    assert_eq!(parcel.read_i32().unwrap(), 12);
+    assert_eq!(parcel.read_i32().unwrap(), 3);
+    assert_eq!(parcel.read_i32().unwrap(), -4);
+    assert_eq!(parcel.read_i64().unwrap(), 4);
+    assert_eq!(parcel.data_position(), len);
+
+    parcel.set_position(0);
+    assert_eq!(Shape::read_value(&mut parcel).unwrap(), shape);
+
+    parcel.clear();
+    Shape::write_nullable_value(None, &mut parcel).unwrap();
+    parcel.set_position(0);
+    assert_eq!(parcel.read_i32().unwrap(), 0);
+    parcel.set_position(0);
+    assert_eq!(Shape::read_nullable_value(&mut parcel).unwrap(), None);
+}
+
+#[test]
+fn derive_struct_skips_unknown_fields() {
+    // A newer writer, with a field we don't know about.
+    let mut parcel = parcel();
+    parcel.write_i32(1);
+    parcel.write_i32(16);
+    parcel.write_i32(3);
+    parcel.write_i32(-4);
+    parcel.write_i32(5);
+    parcel.write_i32(42);
+
+    parcel.set_position(0);
+    assert_eq!(Point::read_value(&mut parcel).unwrap(), Point { x: 3, y: -4 });
+    assert_eq!(parcel.read_i32().unwrap(), 42);
+
+    // An older writer, missing a field.
+    parcel.clear();
+    parcel.write_i32(1);
+    parcel.write_i32(8);
+    parcel.write_i32(3);
+
+    parcel.set_position(0);
+    assert_eq!(Point::read_value(&mut parcel).unwrap(), Point { x: 3, y: 0 });
+}
+
+#[test]
+fn derive_enum() {
+    let mut parcel = parcel();
+    Color::Green.write_value(&mut parcel).unwrap();
+    Big::Huge.write_value(&mut parcel).unwrap();
+    // Enums don't have a marker.
+    assert_eq!(parcel.len(), 12);
+
+    parcel.set_position(0);
+    assert_eq!(parcel.read_i32().unwrap(), 2);
+    assert_eq!(parcel.read_i64().unwrap(), 1 << 40);
+
+    parcel.set_position(0);
+    assert_eq!(Color::read_value(&mut parcel).unwrap(), Color::Green);
+    assert_eq!(Big::read_value(&mut parcel).unwrap(), Big::Huge);
+
+    parcel.clear();
+    parcel.write_i32(3);
+    parcel.set_position(0);
+    assert!(Color::read_value(&mut parcel).is_err());
+}
@@ -67,7 +67,7 @@ impl ActivityManager {
         } else {
             data.write_i32(0);
         }
-        let reply = self.handle.borrow_mut().transact(ActivityManagerProtocol::StartActivity as u32, &mut data, 0)?;
+        let reply = self.handle.borrow().transact(ActivityManagerProtocol::StartActivity as u32, &mut data, 0)?;
         let exception = reply.read_exception();
         reply.read_i32()
     }
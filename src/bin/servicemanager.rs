@@ -0,0 +1,21 @@
+//!
+//! A service manager for systems without Android's. Takes the binder device to
+//! serve as its only argument, `/dev/binder` by default.
+//!
+
+extern crate binder;
+
+use std::env;
+use std::process;
+
+use binder::BinderConnection;
+
+fn main() {
+    let device = env::args().nth(1).unwrap_or_else(|| "/dev/binder".to_owned());
+    let res = BinderConnection::builder().device(&device).open()
+        .and_then(|mut conn| binder::servicemanager::run(&mut conn));
+    if let Err(err) = res {
+        eprintln!("servicemanager: {}: {}", device, err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,150 @@
+//!
+//! Binderfs
+//!
+//! Since Linux 5.0, binder devices don't have to be created when the kernel
+//! boots. Each mount of the `binder` filesystem is a separate binder context,
+//! with a `binder-control` node that creates new devices on demand. This makes
+//! it possible to use binder outside of Android, and to give tests their own
+//! context manager.
+//!
+//! ```no_run
+//! # use binder::BinderConnection;
+//! # use binder::binderfs::BinderFs;
+//! let fs = BinderFs::mount("/tmp/binderfs").unwrap();
+//! let device = fs.add_device("test-binder").unwrap();
+//! let conn = BinderConnection::builder().device(&device).open().unwrap();
+//! ```
+//!
+
+use std;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use nix;
+use nix::mount::{MsFlags, MntFlags};
+
+use {Result, BinderError, BinderErrorKind};
+use sys;
+
+/// The node used to create devices, at the root of every binderfs mount.
+const BINDER_CONTROL : &str = "binder-control";
+
+/// A binderfs instance. If it was mounted by us, it is unmounted on drop.
+#[derive(Debug)]
+pub struct BinderFs {
+    root: PathBuf,
+    mounted: bool
+}
+
+impl BinderFs {
+    /// Mount a new binderfs instance at `target`, creating the directory if
+    /// needed. The instance is detached when the returned `BinderFs` is
+    /// dropped. Its devices are destroyed once nobody has them opened.
+    ///
+    /// This requires `CAP_SYS_ADMIN`, and a kernel built with
+    /// `CONFIG_ANDROID_BINDERFS`.
+    pub fn mount<P: AsRef<Path>>(target: P) -> Result<BinderFs> {
+        let root = target.as_ref().to_path_buf();
+        fs::create_dir_all(&root)?;
+        nix::mount::mount(Some("binder"), &root, Some("binder"), MsFlags::empty(), None::<&str>)?;
+        Ok(BinderFs {
+            root: root,
+            mounted: true
+        })
+    }
+
+    /// Use the binderfs instance already mounted at `root`, such as
+    /// `/dev/binderfs` on recent Android versions. It is left mounted on drop.
+    pub fn attach<P: AsRef<Path>>(root: P) -> Result<BinderFs> {
+        let root = root.as_ref().to_path_buf();
+        if !root.join(BINDER_CONTROL).exists() {
+            return Err(BinderError::from_kind(BinderErrorKind::NoInit).into())
+        }
+        Ok(BinderFs {
+            root: root,
+            mounted: false
+        })
+    }
+
+    /// Where the instance is mounted.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path of the device node called `name`. It doesn't have to exist.
+    pub fn device_path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Create a new binder device, returning the path of its node. Each
+    /// device is a separate binder context, with its own context manager.
+    ///
+    /// # Errors
+    ///
+    /// - `BadValue` if the name is too long, contains a `/` or a NUL byte
+    /// - `AlreadyExists` if a device with this name already exists
+    pub fn add_device(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.len() > sys::BINDERFS_MAX_NAME || name.contains('/') || name.contains('\0') {
+            return Err(BinderError::from_kind(BinderErrorKind::BadValue).into())
+        }
+        let control = File::open(self.root.join(BINDER_CONTROL))?;
+        let mut device : sys::binderfs_device = unsafe { std::mem::zeroed() };
+        device.name[..name.len()].copy_from_slice(name.as_bytes());
+        unsafe { sys::binder_ctl_add(control.as_raw_fd(), &mut device) }
+            .map_err(BinderError::from)?;
+        debug!("Created binder device {} ({}:{})", name, device.major, device.minor);
+        Ok(self.device_path(name))
+    }
+
+    /// Remove the device called `name`. Processes that have it opened keep
+    /// working until they close it.
+    pub fn remove_device(&self, name: &str) -> Result<()> {
+        fs::remove_file(self.device_path(name))?;
+        Ok(())
+    }
+
+    /// Whether the driver backing this instance supports `feature`, as listed
+    /// in the `features` directory. Unknown features aren't supported.
+    pub fn has_feature(&self, feature: &str) -> Result<bool> {
+        let mut file = match File::open(self.root.join("features").join(feature)) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into())
+        };
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        Ok(content.trim() == "1")
+    }
+
+    /// The features supported by the driver backing this instance.
+    pub fn features(&self) -> Result<Vec<String>> {
+        let dir = match fs::read_dir(self.root.join("features")) {
+            Ok(dir) => dir,
+            // Older kernels don't have the features directory.
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into())
+        };
+        let mut features = Vec::new();
+        for entry in dir {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if self.has_feature(&name)? {
+                features.push(name);
+            }
+        }
+        features.sort();
+        Ok(features)
+    }
+}
+
+impl Drop for BinderFs {
+    fn drop(&mut self) {
+        if self.mounted {
+            // Devices may still be in use, by threads that didn't exit yet.
+            if let Err(err) = nix::mount::umount2(&self.root, MntFlags::MNT_DETACH) {
+                warn!("Failed to unmount binderfs at {}: {}", self.root.display(), err);
+            }
+        }
+    }
+}
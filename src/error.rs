@@ -24,17 +24,34 @@ mod binder {
             FdsNotAllowed
             // TODO: Maybe this is not necessary ? I handle NULL with Option
             UnexpectedNull
+            // The driver sent a command we didn't expect, or don't know.
+            UnexpectedCommand {
+                description("Unexpected command from the binder driver")
+                display("Unexpected command from the binder driver")
+            }
             UnknownError(code: i32) {
                 description("Unknown error")
                 display("Unknown error code {}", code)
             }
+            // The binder implements another interface than the one we asked
+            // for.
+            InterfaceMismatch(expected: String, actual: String) {
+                description("Binder implements an unexpected interface")
+                display("Expected interface {}, got {}", expected, actual)
+            }
+            // The service replied with an exception.
+            ServiceException(status: ::status::Status) {
+                description("Service threw an exception")
+                display("{}", status)
+            }
         }
     }
 
+    const UNKNOWN_ERROR : i32 = (-2147483647-1);
+
     impl From<libc::c_int> for Error {
         fn from(err: libc::c_int) -> Error {
             use self::ErrorKind::*;
-            const UNKNOWN_ERROR : i32 = (-2147483647-1);
 
             let kind =
                 if err == -libc::ENOMEM           { NoMemory }
@@ -56,33 +73,67 @@ mod binder {
                 // End todo
                 else if err == UNKNOWN_ERROR + 7  { FdsNotAllowed }
                 else if err == UNKNOWN_ERROR + 8  { UnexpectedNull }
-                else if err < 0                   { UnknownError(err) }
-                else { panic!("From<libc::c_int> for Error called with positive value {}", err) };
+                // Positive values aren't errors the driver or libbinder would
+                // send, but a misbehaving remote might.
+                else                              { UnknownError(err) };
             kind.into()
         }
+    }
 
-        // TODO: Write conversion for nix::Errno
+    // The ioctls fail with a negated errno, which the table above knows how to
+    // map.
+    impl From<::nix::Error> for Error {
+        fn from(err: ::nix::Error) -> Error {
+            match err {
+                ::nix::Error::Sys(errno) => Error::from(-(errno as libc::c_int)),
+                ::nix::Error::UnsupportedOperation => ErrorKind::InvalidOperation.into(),
+                _ => ErrorKind::BadValue.into()
+            }
+        }
     }
-}
 
-/*mod exception {
-    error_chain! {
-        errors {
-            Security
-            BadParcelable
-            IllegalArgument
-            NullPointer
-            IllegalState
-            NetworkMainThread
-            UnsupportedOperation
-            ServiceSpecific
-            RuntimeException(code: i32, s: String) {
-                description("Unknown exception code")
-                display("Unknown exception code: {} msg {}", code, s)
+    // Parcel reads fail with an io::Error when the data is truncated or
+    // malformed.
+    impl From<::std::io::Error> for Error {
+        fn from(err: ::std::io::Error) -> Error {
+            match err.kind() {
+                ::std::io::ErrorKind::UnexpectedEof => ErrorKind::NotEnoughData.into(),
+                _ => ErrorKind::BadValue.into()
+            }
+        }
+    }
+
+    impl ErrorKind {
+        /// Get the status code corresponding to this error, as sent over the
+        /// wire in `STATUS_CODE` replies. This is the inverse of
+        /// `From<libc::c_int>`.
+        pub fn status_code(&self) -> i32 {
+            use self::ErrorKind::*;
+
+            match *self {
+                NoMemory           => -libc::ENOMEM,
+                InvalidOperation   => -libc::ENOSYS,
+                BadValue           => -libc::EINVAL,
+                BadType            => UNKNOWN_ERROR + 1,
+                NameNotFound       => -libc::ENOENT,
+                PermissionDenied   => -libc::EPERM,
+                NoInit             => -libc::ENODEV,
+                AlreadyExists      => -libc::EEXIST,
+                DeadObject         => -libc::EPIPE,
+                FailedTransaction  => UNKNOWN_ERROR + 2,
+                BadIndex           => -libc::EOVERFLOW,
+                NotEnoughData      => -libc::ENODATA,
+                WouldBlock         => -libc::EWOULDBLOCK,
+                TimedOut           => -libc::ETIMEDOUT,
+                UnknownTransaction => -libc::EBADMSG,
+                FdsNotAllowed      => UNKNOWN_ERROR + 7,
+                UnexpectedNull     => UNKNOWN_ERROR + 8,
+                UnknownError(code) => code,
+                _                  => UNKNOWN_ERROR
             }
         }
     }
-}*/
+}
 
 pub use self::binder::{Error as BinderError, ErrorKind as BinderErrorKind, Result as BinderResult};
 
@@ -17,8 +17,14 @@
 //!
 //! In the C++ framework, it is possible to abstract over multiple kinds of
 //! Binder (BpBinder, which represents a Binder in a remote process, and
-//! BbBinder, which represents a local Binder service). This library doesn't
-//! even try to do this. Instead, we focus on the BpBinder aspect (for now).
+//! BbBinder, which represents a local Binder service). This library mostly
+//! focuses on the BpBinder aspect : remote Binders are represented by a
+//! `Handle`. Local services can be exposed by implementing the `LocalBinder`
+//! trait and writing them to a parcel with `OwnedParcel::write_local_binder`.
+//! Incoming transactions are then dispatched to them by the connection.
+//!
+//! Code that doesn't care which side of the process boundary a Binder lives
+//! on can use the `IBinder` enum, which wraps either of them.
 //!
 #![feature(conservative_impl_trait)]
 #![feature(const_fn)]
@@ -40,35 +46,45 @@ extern crate byteorder;
 pub mod sys;
 pub mod error;
 pub mod parcel;
+pub mod parcelable;
+pub mod status;
 pub mod service_manager;
+pub mod local_binder;
+pub mod process_state;
+pub mod binderfs;
+pub mod servicemanager;
 
 use std::cell::RefCell;
-use std::os::unix::io::RawFd;
 use std::collections::HashMap;
 use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use std::mem::size_of;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::hash::{Hash, Hasher};
+use std::io::{Cursor, SeekFrom};
 
 use service_manager::ServiceManager;
+use local_binder::LocalBinder;
+use process_state::ProcessState;
 use error::*;
 use parcel::*;
-
-const BINDER_VM_SIZE : usize = (1024 * 1024) - (4096 * 2);
+use byteorder::{ReadBytesExt, NativeEndian};
 
 // This will be passed around in the various places that need it
 #[derive(Debug)]
 struct BinderConnectionInner {
-    fd: RawFd,
-    vm_start: *mut nix::libc::c_void,
-    /*out: parcel::OwnedParcel,
-    _in: parcel::OwnedParcel,*/
-    handle_map: HashMap<u32, Weak<RefCell<Handle>>>
-}
-
-impl Drop for BinderConnectionInner {
-    fn drop(&mut self) {
-        let _ = nix::unistd::close(self.fd);
-        let _ = unsafe { nix::sys::mman::munmap(self.vm_start, BINDER_VM_SIZE) };
-    }
+    process: Arc<ProcessState>,
+    /// Commands waiting to be sent to the driver.
+    out: Vec<u8>,
+    /// Commands received from the driver. Those before the position were
+    /// already executed.
+    _in: Cursor<Vec<u8>>,
+    handle_map: HashMap<u32, Weak<RefCell<Handle>>>,
+    /// The pid and euid of the process that sent the transaction we are
+    /// handling, or our own outside of transactions.
+    calling_pid: libc::pid_t,
+    calling_uid: libc::uid_t
 }
 
 /// A connection to the Binder kernel interface.
@@ -77,7 +93,10 @@ impl Drop for BinderConnectionInner {
 /// directly. Instead, you should first get an IBinder to the Service Manager by
 /// calling `get_context_object()`. You will then be able to access other
 /// services through the Service Manager.
-// TODO: Rc/Arc the BinderConnection ?
+///
+/// A `BinderConnection` is the per-thread side of the driver, the equivalent
+/// of libbinder's IPCThreadState. Threads that want to talk to the driver
+/// should each create their own from the shared `ProcessState`.
 #[derive(Debug, Clone)]
 pub struct BinderConnection {
     inner: Rc<RefCell<BinderConnectionInner>>
@@ -107,75 +126,237 @@ enum ReturnProtocolValue {
     FailedReply
 }
 
-// Represents a connection to a service. The connection can be Local or Remote.
-// enum IBinder {
-//     Local,
-//     Remote
-// }
+/// A Binder object. Roughly equivalent to an sp<IBinder> in the libbinder
+/// framework.
+///
+/// The object can either live in the current process (`Local`), in which case
+/// transactions are directly dispatched to it, or in another process
+/// (`Remote`), in which case they go through the kernel.
+///
+/// Two `IBinder`s are equal if they point to the same object.
+#[derive(Clone)]
+pub enum IBinder {
+    /// A binder hosted by the current process.
+    Local(Arc<LocalBinder>),
+    /// A binder hosted by another process.
+    Remote(Rc<RefCell<Handle>>)
+}
 
-fn parse_one<T: Parcel>(_in: &mut T) -> Option<ReturnProtocolValue> {
-    use sys::ReturnProtocol::*;
-    match _in.read_i32() {
-        Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => None,
-        Err(_) => panic!("This should never happen !"),
-        std::result::Result::Ok(cmd) => match sys::ReturnProtocol::from_primitive(cmd) {
-            Some(Ok) => Some(ReturnProtocolValue::Ok),
-            Some(Error) => Some(ReturnProtocolValue::Error(_in.read_i32().expect("Binder protocol error"))),
-            Some(x) if x == Transaction || x == Reply => {
-                // FIXME: constexpr size_of
-                let mut buf = [0; size_of::<sys::binder_transaction_data>()];
-                _in.read_buf(&mut buf).expect("Binder protocol error");
-                let txn : sys::binder_transaction_data = unsafe { std::mem::transmute(buf) };
-                if x == Transaction {
-                    Some(ReturnProtocolValue::Transaction(txn))
-                } else {
-                    Some(ReturnProtocolValue::Reply(txn))
-                }
-            },
-            Some(AcquireResult) => Some(ReturnProtocolValue::AcquireResult(_in.read_i32().expect("Binder protocol error"))),
-            Some(DeadReply) => Some(ReturnProtocolValue::DeadReply),
-            Some(TransactionComplete) => Some(ReturnProtocolValue::TransactionComplete),
-            Some(x) if x == IncRefs || x == Acquire || x == Release || x == DecRefs => {
-                // FIXME: constexpr size_of
-                let mut buf = [0; size_of::<sys::binder_ptr_cookie>()];
-                _in.read_buf(&mut buf).expect("Binder protocol error");
-                let ptr : sys::binder_ptr_cookie = unsafe { std::mem::transmute(buf) };
-                match x {
-                    IncRefs => Some(ReturnProtocolValue::IncRefs(ptr)),
-                    Acquire => Some(ReturnProtocolValue::Acquire(ptr)),
-                    Release => Some(ReturnProtocolValue::Release(ptr)),
-                    DecRefs => Some(ReturnProtocolValue::DecRefs(ptr)),
-                    _ => unreachable!()
-                }
-            },
-            Some(AttemptAcquire) => {
-                // FIXME: constexpr size_of
-                let mut buf = [0; size_of::<sys::binder_pri_ptr_cookie>()];
-                _in.read_buf(&mut buf).expect("Binder protocol error");
-                let ptr : sys::binder_pri_ptr_cookie = unsafe { std::mem::transmute(buf) };
-                Some(ReturnProtocolValue::AttemptAcquire(ptr))
-            },
-            Some(Noop) => Some(ReturnProtocolValue::Noop),
-            Some(SpawnLooper) => Some(ReturnProtocolValue::SpawnLooper),
-            Some(Finished) => Some(ReturnProtocolValue::Finished),
-            Some(x) if x == DeadBinder || x == ClearDeathNotificationDone => {
-                // FIXME: constexpr size_of
-                let mut buf = [0; size_of::<sys::binder_uintptr_t>()];
-                _in.read_buf(&mut buf).expect("Binder protocol error");
-                let ptr : sys::binder_uintptr_t = unsafe { std::mem::transmute(buf) };
-                if x == DeadBinder {
-                    Some(ReturnProtocolValue::DeadBinder(ptr))
-                } else {
-                    Some(ReturnProtocolValue::ClearDeathNotificationDone(ptr))
+impl IBinder {
+    /// Send a transaction to this binder, and wait for its reply.
+    ///
+    /// Use `transact_one_way` for transactions that don't get a reply. Passing
+    /// the `ONE_WAY` flag here fails with `InvalidOperation`.
+    pub fn transact<'a, T: 'a + Parcel>(&self, code: u32, data: &mut T, flags: u32) -> BinderResult<Box<Parcel + 'a>> {
+        match *self {
+            IBinder::Local(ref binder) => {
+                if flags & sys::TransactionFlags::ONE_WAY.bits() != 0 {
+                    return Err(BinderErrorKind::InvalidOperation.into())
                 }
+                let mut reply = IBinder::transact_local(&**binder, code, data, flags)?;
+                reply.set_position(0);
+                Ok(Box::new(reply))
             },
-            Some(FailedReply) => Some(ReturnProtocolValue::FailedReply),
-            None => panic!("Unknown Binder command"), // TODO: Protocol Error
-            _ => unreachable!()
+            IBinder::Remote(ref handle) => Ok(Box::new(handle.borrow().transact(code, data, flags)?))
+        }
+    }
+
+    /// Send a one-way transaction to this binder. For remote binders, this
+    /// returns as soon as the driver accepted it.
+    pub fn transact_one_way<T: Parcel>(&self, code: u32, data: &mut T, flags: u32) -> BinderResult<()> {
+        let flags = flags | sys::TransactionFlags::ONE_WAY.bits();
+        match *self {
+            IBinder::Local(ref binder) => IBinder::transact_local(&**binder, code, data, flags).map(|_| ()),
+            IBinder::Remote(ref handle) => handle.borrow().transact_one_way(code, data, flags)
+        }
+    }
+
+    fn transact_local<T: Parcel>(binder: &LocalBinder, code: u32, data: &mut T, flags: u32) -> BinderResult<OwnedParcel> {
+        // The caller just finished writing data. Let the binder read it from
+        // the start.
+        data.data().seek(SeekFrom::Start(0)).expect("Seeking to the start of a parcel can't fail");
+        let mut reply = OwnedParcel::new(data.conn_mut().clone());
+        local_binder::transact(binder, code, data, &mut reply, flags)?;
+        Ok(reply)
+    }
+
+    /// Check that the binder is still alive.
+    pub fn ping(&self) -> BinderResult<()> {
+        match *self {
+            IBinder::Local(_) => Ok(()),
+            IBinder::Remote(ref handle) => handle.borrow().ping()
+        }
+    }
+
+    /// Get the descriptor of the interface implemented by this binder.
+    pub fn get_interface_descriptor(&self) -> BinderResult<String> {
+        match *self {
+            IBinder::Local(ref binder) => Ok(binder.get_interface_descriptor().to_owned()),
+            IBinder::Remote(ref handle) => handle.borrow().get_interface_descriptor()
+        }
+    }
+
+    /// Get a weak reference to this binder.
+    pub fn downgrade(&self) -> WeakIBinder {
+        match *self {
+            IBinder::Local(ref binder) => WeakIBinder::Local(Arc::downgrade(binder)),
+            IBinder::Remote(ref handle) => {
+                let handle = handle.borrow();
+                WeakIBinder::Remote(Rc::new(WeakHandle::new(handle.conn.clone(), handle.handle)))
+            }
+        }
+    }
+
+    /// Whether this binder lives in the current process.
+    pub fn is_local(&self) -> bool {
+        match *self {
+            IBinder::Local(_) => true,
+            IBinder::Remote(_) => false
+        }
+    }
+
+    // The address of the underlying object, used to implement Eq and Hash.
+    fn as_ptr(&self) -> *const u8 {
+        match *self {
+            IBinder::Local(ref binder) => &**binder as *const LocalBinder as *const u8,
+            IBinder::Remote(ref handle) => &**handle as *const RefCell<Handle> as *const u8
+        }
+    }
+}
+
+impl PartialEq for IBinder {
+    fn eq(&self, other: &IBinder) -> bool {
+        self.as_ptr() == other.as_ptr()
+    }
+}
+
+impl Eq for IBinder {}
+
+impl Hash for IBinder {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state)
+    }
+}
+
+impl fmt::Debug for IBinder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IBinder::Local(ref binder) => write!(f, "Local({:?}, {:p})", binder.get_interface_descriptor(), self.as_ptr()),
+            IBinder::Remote(ref handle) => write!(f, "Remote({:?})", handle.borrow().handle)
+        }
+    }
+}
+
+impl From<Arc<LocalBinder>> for IBinder {
+    fn from(binder: Arc<LocalBinder>) -> IBinder {
+        IBinder::Local(binder)
+    }
+}
+
+impl From<Rc<RefCell<Handle>>> for IBinder {
+    fn from(handle: Rc<RefCell<Handle>>) -> IBinder {
+        IBinder::Remote(handle)
+    }
+}
+
+/// A weak reference to a Binder object. Roughly equivalent to a wp<IBinder> in
+/// the libbinder framework.
+///
+/// A weak reference doesn't keep the object alive. It has to be promoted to an
+/// `IBinder` before it can be used, which fails if the object is gone.
+#[derive(Clone)]
+pub enum WeakIBinder {
+    /// A weak reference to a binder hosted by the current process.
+    Local(std::sync::Weak<LocalBinder>),
+    /// A weak reference to a binder hosted by another process.
+    Remote(Rc<WeakHandle>)
+}
+
+impl WeakIBinder {
+    /// Attempt to get a strong reference to the binder. Returns `None` if the
+    /// binder doesn't exist anymore.
+    pub fn promote(&self) -> BinderResult<Option<IBinder>> {
+        match *self {
+            WeakIBinder::Local(ref binder) => Ok(binder.upgrade().map(IBinder::Local)),
+            WeakIBinder::Remote(ref handle) => Ok(handle.promote()?.map(IBinder::Remote))
+        }
+    }
+}
+
+impl fmt::Debug for WeakIBinder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WeakIBinder::Local(_) => write!(f, "Local"),
+            WeakIBinder::Remote(ref handle) => write!(f, "Remote({:?})", handle.handle)
         }
     }
 }
 
+/// Read the next command sent by the driver. Returns `None` once the buffer is
+/// exhausted.
+fn parse_one(version: sys::ProtocolVersion, _in: &mut Cursor<Vec<u8>>) -> BinderResult<Option<ReturnProtocolValue>> {
+    use sys::ReturnProtocol::*;
+    if _in.position() as usize >= _in.get_ref().len() {
+        return std::result::Result::Ok(None)
+    }
+    let cmd = _in.read_i32::<NativeEndian>()?;
+    // A command truncated by the driver fails with NotEnoughData.
+    let val = match sys::ReturnProtocol::from_primitive(cmd, version) {
+        Some(Ok) => ReturnProtocolValue::Ok,
+        Some(Error) => ReturnProtocolValue::Error(_in.read_i32::<NativeEndian>()?),
+        Some(x) if x == Transaction || x == Reply => {
+            let txn = version.read_transaction_data(_in)?;
+            if x == Transaction {
+                ReturnProtocolValue::Transaction(txn)
+            } else {
+                ReturnProtocolValue::Reply(txn)
+            }
+        },
+        Some(AcquireResult) => ReturnProtocolValue::AcquireResult(_in.read_i32::<NativeEndian>()?),
+        Some(DeadReply) => ReturnProtocolValue::DeadReply,
+        Some(TransactionComplete) => ReturnProtocolValue::TransactionComplete,
+        Some(x) if x == IncRefs || x == Acquire || x == Release || x == DecRefs => {
+            let ptr = version.read_ptr_cookie(_in)?;
+            match x {
+                IncRefs => ReturnProtocolValue::IncRefs(ptr),
+                Acquire => ReturnProtocolValue::Acquire(ptr),
+                Release => ReturnProtocolValue::Release(ptr),
+                DecRefs => ReturnProtocolValue::DecRefs(ptr),
+                _ => unreachable!()
+            }
+        },
+        Some(AttemptAcquire) => ReturnProtocolValue::AttemptAcquire(version.read_pri_ptr_cookie(_in)?),
+        Some(Noop) => ReturnProtocolValue::Noop,
+        Some(SpawnLooper) => ReturnProtocolValue::SpawnLooper,
+        Some(Finished) => ReturnProtocolValue::Finished,
+        Some(x) if x == DeadBinder || x == ClearDeathNotificationDone => {
+            let ptr = version.read_pointer(_in)?;
+            if x == DeadBinder {
+                ReturnProtocolValue::DeadBinder(ptr)
+            } else {
+                ReturnProtocolValue::ClearDeathNotificationDone(ptr)
+            }
+        },
+        Some(FailedReply) => ReturnProtocolValue::FailedReply,
+        None => {
+            error!("Unknown command {:#x} from the binder driver", cmd);
+            return Err(BinderErrorKind::UnexpectedCommand.into())
+        },
+        _ => unreachable!()
+    };
+    std::result::Result::Ok(Some(val))
+}
+
+/// Encode the object offsets of a parcel the way the driver expects them : as
+/// an array of `binder_size_t`.
+fn encode_offsets(version: sys::ProtocolVersion, objects: &[usize]) -> Vec<u8> {
+    let mut offsets = Vec::with_capacity(objects.len() * version.pointer_size());
+    for &pos in objects {
+        version.write_pointer(&mut offsets, pos as sys::binder_size_t).expect("Write bigger than usize");
+    }
+    offsets
+}
+
 pub trait IInterface {
     fn get_interface_descriptor() -> &'static str;
     fn from_handle(handle: Rc<RefCell<Handle>>) -> Self;
@@ -196,33 +377,178 @@ pub trait IInterface {
 /// [BinderConnection.get_context_object](struct.BinderConnection.html#method.get_context_object)
 #[derive(Debug)]
 pub struct Handle {
+    handle: u32,
+    conn: BinderConnection,
+    /// The cookies of the death notifications requested through this Handle.
+    death_cookies: Vec<sys::binder_uintptr_t>
+}
+
+/// A weak reference to a remote Binder. Roughly equivalent to a wp<BpBinder>
+/// in the libbinder framework.
+///
+/// The driver only keeps a weak reference to the remote object for as long as
+/// the `WeakHandle` lives, which doesn't prevent the object from going away.
+#[derive(Debug)]
+pub struct WeakHandle {
     handle: u32,
     conn: BinderConnection
 }
 
+impl WeakHandle {
+    fn new(mut conn: BinderConnection, handle: u32) -> WeakHandle {
+        conn.write_handle_command(sys::CommandProtocol::IncRefs, handle);
+        WeakHandle {
+            handle: handle,
+            conn: conn
+        }
+    }
+
+    /// Get a strong reference to the remote binder, or `None` if it is gone.
+    ///
+    /// If a strong proxy for this handle is still alive, it is returned.
+    /// Otherwise, as the Linux driver doesn't implement `AttemptAcquire`, we
+    /// take a strong reference and ping the remote object to check that it
+    /// still exists.
+    pub fn promote(&self) -> BinderResult<Option<Rc<RefCell<Handle>>>> {
+        if let Some(handle) = self.conn.get_existing_proxy_for_handle(self.handle) {
+            return Ok(Some(handle))
+        }
+        let mut conn = self.conn.clone();
+        let handle = conn.get_strong_proxy_for_handle(self.handle);
+        let res = handle.borrow().ping();
+        match res {
+            Ok(()) => Ok(Some(handle)),
+            Err(BinderError(BinderErrorKind::DeadObject, _)) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+impl Drop for WeakHandle {
+    fn drop(&mut self) {
+        self.conn.write_handle_command(sys::CommandProtocol::DecRefs, self.handle);
+        if let Err(err) = self.conn.flush_commands() {
+            warn!("Failed to release weak handle {}: {}", self.handle, err);
+        }
+    }
+}
+
+/// Gets notified when the process hosting a remote binder dies. Roughly
+/// equivalent to libbinder's IBinder::DeathRecipient.
+///
+/// Obituaries are delivered by whichever thread happens to read them from the
+/// driver, which is why recipients have to be `Send` and `Sync`.
+pub trait DeathRecipient: Send + Sync {
+    /// Called when the binder behind `handle` died.
+    fn binder_died(&self, handle: u32);
+}
+
 impl Handle {
-    // TODO: conn->incStrongHandle()
-    fn new(conn: BinderConnection, handle: u32) -> Handle {
+    /// Create the proxy for `handle`, taking a weak and a strong reference to
+    /// it in the driver. Those are released when the Handle is dropped.
+    fn new(mut conn: BinderConnection, handle: u32) -> Handle {
+        // Those go out before the FreeBuffer of the parcel we got the handle
+        // from, so the driver doesn't drop the reference in the meantime.
+        conn.write_handle_command(sys::CommandProtocol::IncRefs, handle);
+        conn.write_handle_command(sys::CommandProtocol::Acquire, handle);
         Handle {
             handle: handle,
-            conn: conn
+            conn: conn,
+            death_cookies: Vec::new()
         }
     }
 
+    /// The connection this handle talks through. Parcels sent to this handle
+    /// should be created from it.
+    pub fn conn(&self) -> &BinderConnection {
+        &self.conn
+    }
+
+    /// Register `recipient` to be notified when the process hosting this
+    /// binder dies.
+    ///
+    /// The same recipient may be registered more than once, in which case it
+    /// will be notified once per registration.
+    pub fn link_to_death(&mut self, recipient: Arc<DeathRecipient>) -> BinderResult<()> {
+        let cookie = self.conn.process().add_death_recipient(self.handle, recipient);
+        self.death_cookies.push(cookie);
+        self.conn.write_death_command(sys::CommandProtocol::RequestDeathNotification, self.handle, cookie);
+        self.conn.flush_commands()
+    }
+
+    /// Unregister a recipient previously registered with `link_to_death`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NameNotFound` if `recipient` wasn't registered through this
+    /// Handle, or if it was already notified.
+    pub fn unlink_to_death(&mut self, recipient: &Arc<DeathRecipient>) -> BinderResult<()> {
+        let process = self.conn.process();
+        let pos = match self.death_cookies.iter().position(|cookie| process.death_recipient_is(*cookie, recipient)) {
+            Some(pos) => pos,
+            None => return Err(BinderErrorKind::NameNotFound.into())
+        };
+        let cookie = self.death_cookies.remove(pos);
+        process.remove_death_recipient(cookie);
+        self.conn.write_death_command(sys::CommandProtocol::ClearDeathNotification, self.handle, cookie);
+        self.conn.flush_commands()
+    }
+
     // TODO: Take a &mut OwnedParcel for the reply ?
     // TODO: Why does T need 'a ?
-    pub fn transact<'a, T: 'a + Parcel>(&mut self, code: u32, data: &mut T, flags: u32) -> BinderResult<impl Parcel + 'a> {
+    /// Send a transaction to the remote binder, and wait for its reply.
+    ///
+    /// Use `transact_one_way` for transactions that don't get a reply. Passing
+    /// the `ONE_WAY` flag here fails with `InvalidOperation`.
+    pub fn transact<'a, T: 'a + Parcel>(&self, code: u32, data: &mut T, flags: u32) -> BinderResult<impl Parcel + 'a> {
+        if flags & sys::TransactionFlags::ONE_WAY.bits() != 0 {
+            return Err(BinderErrorKind::InvalidOperation.into())
+        }
         // TODO: mAlive
-        self.conn.call(self.handle, code, data, flags)
+        // Local binders might call us back while we wait for the reply, so we
+        // shouldn't require a mutable borrow of the Handle here.
+        let mut conn = self.conn.clone();
+        conn.call(self.handle, code, data, flags)
     }
 
-    // TODO: Implement get_interface_descriptor()
+    /// Send a one-way transaction to the remote binder. This returns as soon
+    /// as the driver accepted it.
+    pub fn transact_one_way<T: Parcel>(&self, code: u32, data: &mut T, flags: u32) -> BinderResult<()> {
+        let mut conn = self.conn.clone();
+        conn.call_one_way(self.handle, code, data, flags)
+    }
+
+    /// Check that the remote binder is still alive.
+    pub fn ping(&self) -> BinderResult<()> {
+        let mut data = OwnedParcel::new(self.conn.clone());
+        self.transact(BinderProtocol::PingTransaction as u32, &mut data, 0)?;
+        Ok(())
+    }
+
+    /// Ask the remote binder for the descriptor of the interface it implements.
+    pub fn get_interface_descriptor(&self) -> BinderResult<String> {
+        let mut data = OwnedParcel::new(self.conn.clone());
+        let mut reply = self.transact(BinderProtocol::InterfaceTransaction as u32, &mut data, 0)?;
+        reply.read_string16().map_err(|_| BinderErrorKind::NotEnoughData.into())
+    }
 }
 
-// TODO: conn->decStrongHandle()
 impl Drop for Handle {
     fn drop(&mut self) {
-
+        // The kernel forgets about our death notifications once we drop our
+        // reference, so we have to forget about them too. Those that were
+        // already delivered were cleared at the time.
+        let process = self.conn.process();
+        for cookie in std::mem::replace(&mut self.death_cookies, Vec::new()) {
+            if process.remove_death_recipient(cookie).is_some() {
+                self.conn.write_death_command(sys::CommandProtocol::ClearDeathNotification, self.handle, cookie);
+            }
+        }
+        self.conn.write_handle_command(sys::CommandProtocol::Release, self.handle);
+        self.conn.write_handle_command(sys::CommandProtocol::DecRefs, self.handle);
+        if let Err(err) = self.conn.flush_commands() {
+            warn!("Failed to release handle {}: {}", self.handle, err);
+        }
     }
 }
 
@@ -238,7 +564,6 @@ enum BinderProtocol {
     DumpTransaction = sys::pack_chars(b'_', b'D', b'M', b'P'),
     #[allow(dead_code)] // TODO: remove allow(dead_code)
     ShellCommandTransaction = sys::pack_chars(b'_', b'C', b'M', b'D'),
-    #[allow(dead_code)] // TODO: remove allow(dead_code)
     InterfaceTransaction = sys::pack_chars(b'_', b'N', b'T', b'F'),
     #[allow(dead_code)] // TODO: Remove allow(dead_code)
     SyspropsTransaction = sys::pack_chars(b'_', b'S', b'P', b'R')
@@ -247,6 +572,52 @@ enum BinderProtocol {
 // TODO: This should go somewhere else...
 pub const FIRST_CALL_TRANSACTION : u32 = 1;
 
+/// Opens a `BinderConnection` with non-default settings. See
+/// `BinderConnection::builder`.
+#[derive(Debug, Clone)]
+pub struct BinderConnectionBuilder {
+    device: PathBuf,
+    vm_size: usize,
+    max_threads: Option<u32>
+}
+
+impl BinderConnectionBuilder {
+    /// The binder device node to open. Any binder device works, including the
+    /// nodes of a binderfs instance. Defaults to `/dev/binder`.
+    pub fn device<P: AsRef<Path>>(mut self, device: P) -> BinderConnectionBuilder {
+        self.device = device.as_ref().to_path_buf();
+        self
+    }
+
+    /// The size of the memory mapping incoming transactions are copied into.
+    /// Defaults to `process_state::BINDER_VM_SIZE`, like libbinder.
+    pub fn vm_size(mut self, vm_size: usize) -> BinderConnectionBuilder {
+        self.vm_size = vm_size;
+        self
+    }
+
+    /// Set the maximum amount of threads the driver may ask us to spawn. See
+    /// `ProcessState::set_max_threads`. Defaults to
+    /// `process_state::DEFAULT_MAX_THREADS`.
+    pub fn max_threads(mut self, max_threads: u32) -> BinderConnectionBuilder {
+        self.max_threads = Some(max_threads);
+        self
+    }
+
+    /// Open the device, and create a connection for the current thread.
+    ///
+    /// # Errors
+    ///
+    /// Same as `BinderConnection::open`.
+    pub fn open(self) -> Result<BinderConnection> {
+        let process = ProcessState::open_device(&self.device, self.vm_size)?;
+        if let Some(max_threads) = self.max_threads {
+            process.set_max_threads(max_threads)?;
+        }
+        Ok(BinderConnection::new(process))
+    }
+}
+
 // TODO: Add a non-blocking mode with mio, integrate with tokio !
 impl BinderConnection {
     ///
@@ -261,33 +632,69 @@ impl BinderConnection {
     /// - `Io` if there is an error opening the connection to the driver
     /// - `Nix` if there is an error mmapping the Binder VM
     pub fn open() -> Result<BinderConnection> {
-        use nix::sys::mman::*;
-        use std::os::unix::io::IntoRawFd;
-
-        let fd = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open("/dev/binder")?.into_raw_fd();
-
-        let mut vers : sys::binder_version = unsafe { std::mem::zeroed() };
-        unsafe { sys::binder_version(fd, &mut vers)?; }
-        if vers.protocol_version != sys::CurrentProtocolVersion {
-            error!("Binder driver protocol does not match user space protocol!");
-            let _ = nix::unistd::close(fd);
-            return Err(ErrorKind::WrongProtocolVersion.into())
-        }
-        // So, I need to make sure my theory is right, but I *think* binder
-        // returns pointer to stuff in this zone when it needs to. This means
-        // I need to find those pointer, and bind their lifetime to
-        // BinderConnection...
-        let map = unsafe { mmap(std::ptr::null_mut(), BINDER_VM_SIZE, PROT_READ, MAP_PRIVATE | MAP_NORESERVE, fd, 0)? };
-        Ok(BinderConnection {
+        Ok(BinderConnection::new(ProcessState::open()?))
+    }
+
+    /// Configure a connection to a different device, or with a different VM
+    /// size.
+    ///
+    /// ```no_run
+    /// # use binder::BinderConnection;
+    /// let conn = BinderConnection::builder()
+    ///     .device("/dev/vndbinder")
+    ///     .max_threads(4)
+    ///     .open()
+    ///     .unwrap();
+    /// ```
+    pub fn builder() -> BinderConnectionBuilder {
+        BinderConnectionBuilder {
+            device: PathBuf::from(process_state::DEFAULT_BINDER_DEVICE),
+            vm_size: process_state::BINDER_VM_SIZE,
+            max_threads: None
+        }
+    }
+
+    /// Create a new connection for the current thread, talking to the driver
+    /// opened by `process`.
+    pub fn new(process: Arc<ProcessState>) -> BinderConnection {
+        BinderConnection {
             inner: Rc::new(RefCell::new(BinderConnectionInner {
-                fd: fd,
-                vm_start: map,
-                handle_map: HashMap::default()
+                process: process,
+                out: Vec::with_capacity(256),
+                _in: Cursor::new(Vec::with_capacity(256)),
+                handle_map: HashMap::default(),
+                calling_pid: unsafe { libc::getpid() },
+                calling_uid: unsafe { libc::getuid() }
             }))
-        })
+        }
+    }
+
+    /// Get the state shared by every connection of this process.
+    pub fn process(&self) -> Arc<ProcessState> {
+        self.inner.borrow().process.clone()
+    }
+
+    fn fd(&self) -> std::os::unix::io::RawFd {
+        self.inner.borrow().process.fd()
+    }
+
+    /// The pid of the process that sent the transaction being handled by the
+    /// current thread. Outside of transactions, this is our own pid.
+    pub fn calling_pid(&self) -> libc::pid_t {
+        self.inner.borrow().calling_pid
+    }
+
+    /// The effective uid of the process that sent the transaction being
+    /// handled by the current thread. Outside of transactions, this is our own
+    /// uid.
+    pub fn calling_uid(&self) -> libc::uid_t {
+        self.inner.borrow().calling_uid
+    }
+
+    /// The protocol version implemented by the driver. See
+    /// `ProcessState::protocol_version`.
+    pub fn protocol_version(&self) -> sys::ProtocolVersion {
+        self.inner.borrow().process.protocol_version()
     }
 
     /// Gets a `Handle` to the current context object, or None if it wasn't
@@ -302,9 +709,8 @@ impl BinderConnection {
         if let Err(BinderError(BinderErrorKind::DeadObject, _)) = self.call(0, BinderProtocol::PingTransaction as u32, &mut data, 0) {
             return None
         }
-        println!("Got context object");
-        // TODO: Should I incStrong here ?
-        Some(Rc::new(RefCell::new(Handle::new(self.clone(), 0))))
+        trace!("Got context object");
+        Some(self.get_strong_proxy_for_handle(0))
     }
 
 
@@ -324,7 +730,7 @@ impl BinderConnection {
     fn get_strong_proxy_for_handle(&mut self, handle: u32) -> Rc<RefCell<Handle>> {
         // If we already have a handle, upgrade to a strong handle and return
         // the reference
-        if let Some(e) = self.inner.borrow().handle_map.get(&handle).and_then(|e| e.upgrade()) {
+        if let Some(e) = self.get_existing_proxy_for_handle(handle) {
             return e
         }
 
@@ -335,151 +741,453 @@ impl BinderConnection {
         e
     }
 
-    // TODO: Why does this not take just some raw &mut [u8] ? I mean, parcel is
-    // not *technically* required here
-    fn binder_send_receive_bufs<'out, '_in>(&self, out_opt: Option<&'out mut Parcel>, mut in_opt: Option<&'_in mut OwnedParcel>) {
-        let mut bwr : sys::binder_write_read = unsafe { std::mem::zeroed() };
+    /// Get the Handle object for `handle`, if it is still alive.
+    fn get_existing_proxy_for_handle(&self, handle: u32) -> Option<Rc<RefCell<Handle>>> {
+        self.inner.borrow().handle_map.get(&handle).and_then(|e| e.upgrade())
+    }
+
+    /// Register a local binder in the object table, so that transactions sent
+    /// to it can be routed back. Returns the ptr identifying it to the kernel.
+    fn register_local_binder(&mut self, binder: &Arc<LocalBinder>) -> sys::binder_uintptr_t {
+        self.inner.borrow().process.register_local_binder(binder)
+    }
+
+    /// Get the local binder registered with the given ptr, if any.
+    fn get_local_binder(&self, ptr: sys::binder_uintptr_t) -> Option<Arc<LocalBinder>> {
+        self.inner.borrow().process.get_local_binder(ptr)
+    }
 
+    /// Queue the commands written in `cmds`. They are sent to the driver the
+    /// next time we talk to it.
+    fn queue_commands(&self, cmds: &mut OwnedParcel) {
+        self.inner.borrow_mut().out.extend_from_slice(cmds.as_data_slice_mut());
+    }
+
+    /// Send the queued commands to the driver and, if `do_receive` is true,
+    /// wait for it to send us some.
+    ///
+    /// Nothing is read until the previously received commands were all
+    /// executed, since they would get overwritten. Commands the driver didn't
+    /// consume stay queued.
+    fn talk_with_driver(&self, do_receive: bool) -> BinderResult<()> {
+        let fd = self.fd();
+        let version = self.protocol_version();
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+
+        let need_read = inner._in.position() as usize >= inner._in.get_ref().len();
+        // Don't write anything while we still have commands to execute, unless
+        // we aren't going to read : the queued commands might be answers to
+        // the next ones.
+        let out_avail = if !do_receive || need_read { inner.out.len() } else { 0 };
+
+        let mut bwr : sys::binder_write_read = unsafe { std::mem::zeroed() };
         // The write_buffer is never written to in the kernel code, so having a
         // const reference is OK here.
-        if let Some(out) = out_opt {
-            bwr.write_size = out.as_data_slice_mut().len() as sys::binder_size_t;
-            bwr.write_buffer = out.as_data_slice_mut().as_ptr() as sys::binder_uintptr_t;
+        bwr.write_size = out_avail as sys::binder_size_t;
+        bwr.write_buffer = inner.out.as_ptr() as sys::binder_uintptr_t;
+        if do_receive && need_read {
+            bwr.read_size = inner._in.get_ref().capacity() as sys::binder_size_t;
+            bwr.read_buffer = inner._in.get_mut().as_mut_ptr() as sys::binder_uintptr_t;
         }
-        {
-            if let Some(ref mut _in) = in_opt {
-                bwr.read_size = _in.capacity() as sys::binder_size_t;
-                // This is only necessary until the end of the ioctl call just
-                // bellow. I'd love it if it could actually have a lifetime.
-                bwr.read_buffer = _in.as_data_slice_mut().as_ptr() as sys::binder_uintptr_t;
-                bwr.read_consumed = 0;
-            }
-            trace!("Calling binder_write_read with bwr write_size = {}, read_size = {}", bwr.write_size, bwr.read_size);
-            unsafe {
-                // TODO: Map this to a BinderError
-                // TODO: Loop on -eintr
-                sys::binder_write_read(self.inner.borrow().fd, &mut bwr)
-                    .expect("TODO: Figure out IOCTL error codes");
+        if bwr.write_size == 0 && bwr.read_size == 0 {
+            return Ok(())
+        }
+
+        trace!("Calling binder_write_read with bwr write_size = {}, read_size = {}", bwr.write_size, bwr.read_size);
+        // The driver updates write_consumed and read_consumed even when it gets
+        // interrupted, and resumes from there when we call it again.
+        let res = loop {
+            match unsafe { sys::write_read(fd, version, &mut bwr) } {
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                res => break res
             }
         };
 
-        if let Some(_in) = in_opt {
-            unsafe { _in.set_data_len(bwr.read_consumed as usize) };
-            _in.set_position(0);
+        inner.out.drain(..bwr.write_consumed as usize);
+        if bwr.read_consumed > 0 {
+            unsafe { inner._in.get_mut().set_len(bwr.read_consumed as usize) };
+            inner._in.set_position(0);
         }
+        res?;
+        Ok(())
+    }
+
+    /// Get the next command sent by the driver, if there is any left to
+    /// execute.
+    fn next_command(&self) -> BinderResult<Option<ReturnProtocolValue>> {
+        let version = self.protocol_version();
+        let mut inner = self.inner.borrow_mut();
+        let res = parse_one(version, &mut inner._in);
+        if res.is_err() {
+            // We can't know where the next command starts, so the rest of the
+            // buffer is lost.
+            let len = inner._in.get_ref().len();
+            inner._in.set_position(len as u64);
+        }
+        res
+    }
+
+    /// Send the queued commands to the driver right away, without waiting for
+    /// anything in return.
+    ///
+    /// Reference count changes and the freeing of received buffers are only
+    /// queued, and normally go out with the next transaction. Threads that
+    /// don't talk to the driver often might want to flush them.
+    pub fn flush_commands(&mut self) -> BinderResult<()> {
+        self.talk_with_driver(false)
     }
 
     // TODO: Does it really need &mut ? What about &mut Parcel
-    // TODO: This should return a BinderResult.
-    fn call<'a, T: Parcel>(&mut self, handle: u32, code: u32, msg: &mut T, _/* TODO: flags */: u32) -> BinderResult<impl Parcel + 'a> {
+    /// Send a transaction to `handle`, wait for its reply and return it.
+    fn call<'a, T: Parcel>(&mut self, handle: u32, code: u32, msg: &mut T, flags: u32) -> BinderResult<impl Parcel + 'a> {
+        let flags = flags & !sys::TransactionFlags::ONE_WAY.bits();
+        // The driver is done with msg once it sent the TransactionComplete.
+        let _offsets = self.queue_transaction(handle, code, msg, flags);
+        match self.wait_for_response(true)? {
+            Some(reply) => Ok(reply),
+            None => unreachable!("wait_for_response(true) only returns with a reply")
+        }
+    }
+
+    /// Send a `ONE_WAY` transaction to `handle`, returning once the driver
+    /// accepted it.
+    fn call_one_way<T: Parcel>(&mut self, handle: u32, code: u32, msg: &mut T, flags: u32) -> BinderResult<()> {
+        let flags = flags | sys::TransactionFlags::ONE_WAY.bits();
+        let _offsets = self.queue_transaction(handle, code, msg, flags);
+        self.wait_for_response(false)?;
+        Ok(())
+    }
+
+    /// Queue a `Transaction` command. The returned offsets array is referenced
+    /// by the command, and has to outlive it.
+    fn queue_transaction<T: Parcel>(&mut self, handle: u32, code: u32, msg: &mut T, flags: u32) -> Vec<u8> {
         // TODO: In rust-binder, you can never have an error at the Parcel level
         // Ensure that is true !
 
-        // TODO: flags |= TF_ACCEPT_FDS
+        let version = self.protocol_version();
+        // This has to live until the driver is done with msg.
+        let offsets = encode_offsets(version, msg.as_objects_slice_mut());
 
         let mut data : sys::binder_transaction_data = unsafe { std::mem::zeroed() };
         data.target.handle = handle;
         data.code = code;
+        data.flags = flags | sys::TransactionFlags::ACCEPT_FDS.bits();
         // In the linux kernel, the pointer is casted to a (const void __user *)
         // If I understand things correctly, this means it's OK to just use a
         // non-mutable reference
         data.data_size = msg.as_data_slice_mut().len() as sys::binder_size_t;
-        data.offsets_size = msg.as_objects_slice_mut().len() as sys::binder_size_t;
+        // The kernel wants the size of the offsets array in bytes.
+        data.offsets_size = offsets.len() as sys::binder_size_t;
         // TODO: Support sending errors to the remote process. It's a bit weird
         // but there is this thing called the statusBuffer ?
         data.buffer = msg.as_data_slice_mut().as_ptr() as sys::binder_uintptr_t;
-        data.offsets = msg.as_objects_slice_mut().as_ptr() as sys::binder_uintptr_t;
+        data.offsets = offsets.as_ptr() as sys::binder_uintptr_t;
 
         let mut out = OwnedParcel::new(self.clone());
-        let mut _in = OwnedParcel::new(self.clone());
+        out.write_u32(sys::CommandProtocol::Transaction.code(version));
+        out.write_transaction_data(&data);
+        self.queue_commands(&mut out);
+        offsets
+    }
+
+    /// Send a reply to the transaction we are currently handling.
+    ///
+    /// If `status` is an error, the reply data is discarded and the status code
+    /// corresponding to the error is sent instead.
+    fn send_reply(&mut self, reply: &mut OwnedParcel, status: BinderResult<()>) -> BinderResult<()> {
+        // This has to live until the command was consumed by the driver.
+        let status_buffer : i32 = match status {
+            Ok(()) => 0,
+            Err(ref err) => err.kind().status_code()
+        };
+        let version = self.protocol_version();
+        let offsets = encode_offsets(version, reply.as_objects_slice_mut());
 
-        out.write_u32(sys::CommandProtocol::Transaction as u32);
-        unsafe {
-            out.write_buf(&std::mem::transmute::<sys::binder_transaction_data, [u8; size_of::<sys::binder_transaction_data>()]>(data));
+        let mut data : sys::binder_transaction_data = unsafe { std::mem::zeroed() };
+        // Replies don't have a target, the kernel knows who to send them to.
+        data.target.handle = !0;
+        if status.is_ok() {
+            data.data_size = reply.as_data_slice_mut().len() as sys::binder_size_t;
+            data.offsets_size = offsets.len() as sys::binder_size_t;
+            data.buffer = reply.as_data_slice_mut().as_ptr() as sys::binder_uintptr_t;
+            data.offsets = offsets.as_ptr() as sys::binder_uintptr_t;
+        } else {
+            data.flags = sys::TransactionFlags::STATUS_CODE.bits();
+            data.data_size = size_of::<i32>() as sys::binder_size_t;
+            data.buffer = &status_buffer as *const i32 as sys::binder_uintptr_t;
         }
 
-        self.binder_send_receive_bufs(Some(&mut out), Some(&mut _in));
+        let mut out = OwnedParcel::new(self.clone());
+        out.write_u32(sys::CommandProtocol::Reply.code(version));
+        out.write_transaction_data(&data);
+        self.queue_commands(&mut out);
+
+        self.wait_for_response(false)?;
+        Ok(())
+    }
+
+    /// Process the commands sent by the driver until our last command is
+    /// answered. If `want_reply` is true, we wait until a `Reply` is received
+    /// and return it. Otherwise, we stop at the `TransactionComplete`.
+    fn wait_for_response<'a>(&mut self, want_reply: bool) -> BinderResult<Option<BinderParcel<'a>>> {
         loop {
-            loop {
-                let one = parse_one(&mut _in);
-                println!("Received one {:?}", one);
-                match one {
-                    Some(ReturnProtocolValue::TransactionComplete) => {
-                        // TODO: if !reply && !acquireResult => break,
+            self.talk_with_driver(true)?;
+            while let Some(cmd) = self.next_command()? {
+                trace!("Received one {:?}", cmd);
+                match cmd {
+                    ReturnProtocolValue::TransactionComplete => {
+                        // Whatever is left in the buffer gets executed the
+                        // next time we wait for the driver.
+                        if !want_reply {
+                            return Ok(None)
+                        }
                     },
-                    Some(ReturnProtocolValue::DeadReply) => {
+                    ReturnProtocolValue::DeadReply => {
                         return Err(BinderErrorKind::DeadObject.into())
                     },
-                    Some(ReturnProtocolValue::FailedReply) => {
+                    ReturnProtocolValue::FailedReply => {
                         return Err(BinderErrorKind::FailedTransaction.into())
                     },
                     // TODO: AcquireResult => Needs BinderRc
-                    Some(ReturnProtocolValue::Reply(txn)) => {
+                    ReturnProtocolValue::Reply(txn) => {
                         let mut buffer = unsafe {
                             parcel::create_binder_parcel(self.clone(), txn.buffer as *mut u8,
                                           txn.data_size as usize,
-                                          txn.offsets as *mut usize,
+                                          txn.offsets as *const u8,
                                           txn.offsets_size as usize)
                         };
+                        if !want_reply {
+                            // Nobody is interested in this. Dropping the
+                            // buffer frees it.
+                            continue;
+                        }
                         if txn.flags & sys::TransactionFlags::STATUS_CODE.bits() == 0 {
-                            println!("Returning from call");
-                            return Ok(buffer)
+                            trace!("Returning from call");
+                            return Ok(Some(buffer))
                         } else {
-                            let err = buffer.read_i32().expect("There should always be an error code in case of error.");
-                            // TODO: Map to BinderResult
-                            return Err(BinderErrorKind::UnknownError(err).into())
+                            return Err(BinderError::from(buffer.read_i32()?))
                         }
                     },
-                    Some(val) => self.execute_command(val)?,
-                    None => break
+                    val => self.execute_command(val)?
                 }
             }
-            self.binder_send_receive_bufs(None, Some(&mut _in));
+        }
+    }
+
+    /// Wait for the driver to send us some commands, and execute them. This is
+    /// how incoming transactions get dispatched to the local binders.
+    ///
+    /// The calling thread should have entered the looper beforehand, otherwise
+    /// the driver will complain about it waiting for process work. Most users
+    /// will want `join_thread_pool` instead.
+    pub fn get_and_execute_command(&mut self) -> BinderResult<()> {
+        self.talk_with_driver(true)?;
+        while let Some(cmd) = self.next_command()? {
+            trace!("Executing {:?}", cmd);
+            self.execute_command(cmd)?;
+        }
+        Ok(())
+    }
+
+    /// Turn the current thread into a looper, handling incoming transactions
+    /// until the driver tells us to stop.
+    ///
+    /// `is_main` should only be true for the thread that started the thread
+    /// pool (it sends `EnterLooper` instead of `RegisterLooper`). The main
+    /// thread never exits on its own, while other threads exit when the
+    /// driver sends them a `Finished`.
+    ///
+    /// Errors while executing commands are logged. This only returns an error
+    /// when talking to the driver fails.
+    pub fn join_thread_pool(&mut self, is_main: bool) -> BinderResult<()> {
+        self.write_looper_command(if is_main {
+            sys::CommandProtocol::EnterLooper
+        } else {
+            sys::CommandProtocol::RegisterLooper
+        });
+
+        // Like libbinder, a transaction going wrong (e.g. its caller dying
+        // before we reply) doesn't stop the looper. Only failing to talk to
+        // the driver does, since our fd is unusable then.
+        let res = 'looper: loop {
+            if let Err(err) = self.talk_with_driver(true) {
+                break Err(err)
+            }
+            loop {
+                let cmd = match self.next_command() {
+                    Ok(Some(cmd)) => cmd,
+                    Ok(None) => break,
+                    Err(err) => {
+                        error!("Failed to read a command from the binder driver: {}", err);
+                        break
+                    }
+                };
+                trace!("Executing {:?}", cmd);
+                match self.execute_command(cmd) {
+                    Ok(()) => (),
+                    Err(BinderError(BinderErrorKind::TimedOut, _)) if !is_main => break 'looper Ok(()),
+                    Err(err) => warn!("Binder command failed: {}", err)
+                }
+            }
+        };
+
+        self.write_looper_command(sys::CommandProtocol::ExitLooper);
+        // Don't hide the error that made us exit behind this one.
+        let exit = self.flush_commands();
+        res.and(exit)
+    }
+
+    fn write_looper_command(&mut self, cmd: sys::CommandProtocol) {
+        let mut out = OwnedParcel::new(self.clone());
+        out.write_u32(cmd.code(self.protocol_version()));
+        self.queue_commands(&mut out);
+    }
+
+    /// Queue one of the handle reference counting commands, which all take a
+    /// `u32` handle.
+    fn write_handle_command(&mut self, cmd: sys::CommandProtocol, handle: u32) {
+        let mut out = OwnedParcel::new(self.clone());
+        out.write_u32(cmd.code(self.protocol_version()));
+        out.write_u32(handle);
+        self.queue_commands(&mut out);
+    }
+
+    /// Queue one of the commands acknowledging a local binder reference count
+    /// change, which all take a `binder_ptr_cookie`.
+    fn write_ptr_cookie_command(&mut self, cmd: sys::CommandProtocol, ptr: sys::binder_ptr_cookie) {
+        let mut out = OwnedParcel::new(self.clone());
+        out.write_u32(cmd.code(self.protocol_version()));
+        out.write_pointer(ptr.ptr);
+        out.write_pointer(ptr.cookie);
+        self.queue_commands(&mut out);
+    }
+
+    /// Queue one of the death notification commands, which all take a
+    /// `binder_handle_cookie`.
+    fn write_death_command(&mut self, cmd: sys::CommandProtocol, handle: u32, cookie: sys::binder_uintptr_t) {
+        let mut out = OwnedParcel::new(self.clone());
+        out.write_u32(cmd.code(self.protocol_version()));
+        // binder_handle_cookie is packed, so we can't just pad it.
+        out.write_u32(handle);
+        out.write_pointer(cookie);
+        self.queue_commands(&mut out);
+    }
+
+    /// Deliver an obituary to the recipient it was registered for, and
+    /// acknowledge it.
+    fn send_obituary(&mut self, cookie: sys::binder_uintptr_t) {
+        if let Some((handle, recipient)) = self.process().remove_death_recipient(cookie) {
+            recipient.binder_died(handle);
+            // The notification stays registered until we clear it, like
+            // libbinder does.
+            self.write_death_command(sys::CommandProtocol::ClearDeathNotification, handle, cookie);
+        } else {
+            warn!("Received an obituary for unknown cookie {:#x}", cookie);
+        }
+
+        let mut out = OwnedParcel::new(self.clone());
+        out.write_u32(sys::CommandProtocol::DeadBinderDone.code(self.protocol_version()));
+        out.write_pointer(cookie);
+        self.queue_commands(&mut out);
+    }
+
+    /// Tell the driver the current thread is going away, so it can free the
+    /// resources associated with it. The connection shouldn't be used after
+    /// this. See `sys::binder_thread_exit`.
+    pub fn thread_exit(&mut self) {
+        let _ = unsafe { sys::binder_thread_exit(self.fd(), &0) };
+    }
+
+    /// Dispatch an incoming transaction to the local binder it targets, and
+    /// send back its reply.
+    fn execute_transaction(&mut self, txn: sys::binder_transaction_data) -> BinderResult<()> {
+        // The buffer gets freed when this goes out of scope, after the reply
+        // was sent.
+        let mut data = unsafe {
+            parcel::create_binder_parcel(self.clone(), txn.buffer as *mut u8,
+                                         txn.data_size as usize,
+                                         txn.offsets as *const u8,
+                                         txn.offsets_size as usize)
+        };
+        let mut reply = OwnedParcel::new(self.clone());
+        // The kernel only lets us send fds back if the caller accepts them.
+        reply.set_allow_fds(txn.flags & sys::TransactionFlags::ACCEPT_FDS.bits() != 0);
+        let ptr = unsafe { txn.target.ptr };
+        // Local binders may call other binders, which may call us back, so
+        // the identity has to be restored afterwards.
+        let caller = {
+            let mut inner = self.inner.borrow_mut();
+            let caller = (inner.calling_pid, inner.calling_uid);
+            inner.calling_pid = txn.sender_pid;
+            inner.calling_uid = txn.sender_euid;
+            caller
+        };
+        let status = match self.get_local_binder(ptr) {
+            Some(binder) => local_binder::transact(&*binder, txn.code, &mut data, &mut reply, txn.flags),
+            None => {
+                error!("Received a transaction for unknown local binder {:#x}", ptr);
+                Err(BinderErrorKind::UnknownTransaction.into())
+            }
+        };
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.calling_pid = caller.0;
+            inner.calling_uid = caller.1;
+        }
+        if txn.flags & sys::TransactionFlags::ONE_WAY.bits() == 0 {
+            self.send_reply(&mut reply, status)
+        } else {
+            if let Err(err) = status {
+                warn!("One-way transaction {} to {:#x} failed: {}", txn.code, ptr, err);
+            }
+            Ok(())
         }
     }
 
     fn execute_command(&mut self, cmd: ReturnProtocolValue) -> BinderResult<()> {
         match cmd {
-            ReturnProtocolValue::Error(x) => {
-                // TODO: map x to BinderResult
-                Err(BinderErrorKind::UnknownError(x).into())
-            },
+            ReturnProtocolValue::Error(x) => Err(BinderError::from(x)),
             ReturnProtocolValue::Ok => Ok(()),
-            ReturnProtocolValue::Acquire(_) => {
-                // TODO: Do stuff
-                Ok(())
-            },
-            ReturnProtocolValue::Release(_) => {
-                // push cookie to mPendingStrongDerefs
+            // The driver tells us when other processes start and stop
+            // referencing our local binders. As long as they do, the object
+            // table keeps the binder alive.
+            ReturnProtocolValue::Acquire(ptr) => {
+                self.process().inc_local_refs(ptr.ptr, true);
+                self.write_ptr_cookie_command(sys::CommandProtocol::AcquireDone, ptr);
                 Ok(())
             },
-            ReturnProtocolValue::IncRefs(_) => {
-                // TODO: increase ptr, reply
+            ReturnProtocolValue::Release(ptr) => {
+                self.process().dec_local_refs(ptr.ptr, true);
                 Ok(())
             },
-            ReturnProtocolValue::DecRefs(_) => {
-                // Add cookie to mPendingWeakDerefs
+            ReturnProtocolValue::IncRefs(ptr) => {
+                self.process().inc_local_refs(ptr.ptr, false);
+                self.write_ptr_cookie_command(sys::CommandProtocol::IncRefsDone, ptr);
                 Ok(())
             },
-            ReturnProtocolValue::AttemptAcquire(_) => {
-                // attempt acquiring ptr. Make sure it's == obj.
+            ReturnProtocolValue::DecRefs(ptr) => {
+                self.process().dec_local_refs(ptr.ptr, false);
                 Ok(())
             },
-            // Transact. We don't need this, for now. We will when we start
-            // exposing services.
-            ReturnProtocolValue::Transaction(_) => {
-                // Transact. I wonder if it's possible to do this safely.
-                // It looks like libbinder passes pointers around like they're
-                // cookies. Pun intended.
+            ReturnProtocolValue::AttemptAcquire(ptr) => {
+                let success = self.process().attempt_inc_local_strong(ptr.ptr);
+                let mut out = OwnedParcel::new(self.clone());
+                out.write_u32(sys::CommandProtocol::AcquireResult.code(self.protocol_version()));
+                out.write_i32(success as i32);
+                self.queue_commands(&mut out);
                 Ok(())
             },
-            ReturnProtocolValue::DeadBinder(_) => {
-                // send obituary
-                // reply
+            // libbinder passes pointers around like they're cookies. Pun
+            // intended. We only use them as keys in our object table.
+            ReturnProtocolValue::Transaction(txn) => self.execute_transaction(txn),
+            ReturnProtocolValue::DeadBinder(cookie) => {
+                self.send_obituary(cookie);
                 Ok(())
             },
             ReturnProtocolValue::ClearDeathNotificationDone(_) => {
-                // getWeakRefs()->decWeak(proxy)
+                // The recipient was already removed when we sent the
+                // ClearDeathNotification. Nothing left to do.
                 Ok(())
             },
             ReturnProtocolValue::Finished => {
@@ -487,54 +1195,24 @@ impl BinderConnection {
             },
             ReturnProtocolValue::Noop => Ok(()),
             ReturnProtocolValue::SpawnLooper => {
-                // process->spawnPooledThread(false)
+                ProcessState::spawn_requested(&self.process());
                 Ok(())
             },
-            cmd => panic!("Unexpected command {:?}", cmd)
+            cmd => {
+                error!("Unexpected command {:?} from the binder driver", cmd);
+                Err(BinderErrorKind::UnexpectedCommand.into())
+            }
         }
     }
 
     // Unless we talk with the driver, it shouldn't have to allocate stuff
     // to me anyway. So it's ok if we wait until the next call to send it.
-    fn free_buffer(&mut self, buf: *mut u8) -> Result<()> {
+    fn free_buffer(&mut self, buf: *mut u8) {
         let mut out = OwnedParcel::new(self.clone());
-
-        out.write_u32(sys::CommandProtocol::FreeBuffer as u32);
+        out.write_u32(sys::CommandProtocol::FreeBuffer.code(self.protocol_version()));
         out.write_pointer(buf as sys::binder_uintptr_t);
-        self.binder_send_receive_bufs(Some(&mut out), None);
-        Ok(())
+        self.queue_commands(&mut out);
     }
-
-    /*fn talk_with_driver(&self, doReceive: bool) -> Result<()> {
-        let bwr : binder_write_read = std::mem::zeroed();
-        let err = {
-            bwr.write_size = self.out.data.get_ref().len();
-            bwr.write_buffer = self.out.data.get_mut().as_mut_ptr();
-            if doReceive && self._in.data_pos >= self._in.data.len() {
-                bwr.read_size = self._in.data.get_ref().capacity();
-                bwr.read_buffer = self._in.data.get_mut().as_mut_ptr();
-            }
-            loop {
-                err = sys::binder_write_read(self.fd, &mut bwr);
-                match err {
-                    nix::Error::Sys(Errno::EINTR) => continue,
-                    _ => break
-                }
-            }
-        if let Ok(_) = err {
-            // Kind of crazy. Better be careful about this
-            if bwr.write_consumed >= out.data.len() {
-                out.set_data_size(0);
-            } else {
-                assert!("Something weird happen. In Android source, this is not supposed to happen. CF https://github.com/LineageOS/android_frameworks_native/blob/1eed0779cf/libs/binder/IPCThreadState.cpp#L888");
-            if bwr.read_consumed > 0 {
-                unsafe { _in.data.get_mut().set_len(bwr.read_consumed) };
-                _in.data.set_position(0);
-            }
-        } else {
-            err
-        }
-    }*/
 }
 
 // TODO: Develop BinderRc<T>
@@ -0,0 +1,59 @@
+//!
+//! Local Binder
+//!
+//! A local binder is a Binder service living in the current process. It is the
+//! equivalent of a BBinder in the libbinder framework. Other processes never
+//! see the object itself : the kernel only knows it as a ptr/cookie pair, and
+//! hands those back to us whenever someone sends a transaction to it.
+//!
+
+use std::sync::Arc;
+
+use {BinderProtocol, BinderResult};
+use parcel::{Parcel, OwnedParcel};
+use sys;
+
+/// A Binder service hosted in the current process.
+///
+/// Implementors only need to handle their own transaction codes. The
+/// transactions every Binder is expected to answer (`PingTransaction` and
+/// `InterfaceTransaction`) are handled for them.
+///
+/// Transactions may be dispatched from any thread talking to the driver, which
+/// is why local binders have to be `Send` and `Sync`.
+pub trait LocalBinder: Send + Sync {
+    /// The descriptor of the interface implemented by this binder, e.g.
+    /// `android.os.IServiceManager`.
+    fn get_interface_descriptor(&self) -> &str;
+
+    /// Handle an incoming transaction.
+    ///
+    /// `data` contains the arguments sent by the caller. The result of the
+    /// call should be written to `reply`. Returning an error sends a status
+    /// code back to the caller instead of `reply`.
+    fn on_transact(&self, code: u32, data: &mut Parcel, reply: &mut OwnedParcel, flags: u32) -> BinderResult<()>;
+}
+
+/// Dispatch a transaction to a local binder, answering the builtin
+/// transactions on its behalf.
+pub(crate) fn transact(binder: &LocalBinder, code: u32, data: &mut Parcel, reply: &mut OwnedParcel, flags: u32) -> BinderResult<()> {
+    if code == BinderProtocol::PingTransaction as u32 {
+        Ok(())
+    } else if code == BinderProtocol::InterfaceTransaction as u32 {
+        reply.write_string16(binder.get_interface_descriptor());
+        Ok(())
+    } else {
+        binder.on_transact(code, data, reply, flags)
+    }
+}
+
+/// Get the value used as both ptr and cookie to identify `binder` to the
+/// kernel.
+///
+/// libbinder uses the address of the weak refs as ptr and the address of the
+/// object as cookie. We don't have weak refs of our own, so the address of the
+/// object is used for both. It is stable for as long as the connection keeps
+/// the binder alive in its object table.
+pub(crate) fn binder_ptr(binder: &Arc<LocalBinder>) -> sys::binder_uintptr_t {
+    &**binder as *const LocalBinder as *const u8 as usize as sys::binder_uintptr_t
+}
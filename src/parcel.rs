@@ -1,13 +1,18 @@
 use std;
+use std::fs::File;
 use std::io::{Read, Write, Seek, SeekFrom, Cursor};
+use std::os::unix::io::{RawFd, AsRawFd, IntoRawFd, FromRawFd};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::{self, Arc};
 use std::mem::size_of;
 
 use byteorder::{ReadBytesExt, WriteBytesExt, NativeEndian};
 use encoding::codec::utf_16::UTF_16LE_ENCODING;
 use encoding::{Encoding, EncoderTrap, DecoderTrap};
-use {BinderConnection, Result, BinderResult, BinderError, BinderErrorKind, Handle};
+use libc;
+use {BinderConnection, Result, BinderResult, BinderError, BinderErrorKind, IBinder, WeakIBinder, WeakHandle};
+use local_binder::LocalBinder;
+use status::Status;
 use sys::{self, flat_binder_object};
 
 macro_rules! pad_size {
@@ -17,6 +22,31 @@ macro_rules! pad_size {
     }}
 }
 
+/// The header of interface tokens written by system processes.
+pub const INTERFACE_HEADER_SYSTEM : u32 = sys::pack_chars(b'S', b'Y', b'S', b'T');
+/// The header of interface tokens written by vendor processes.
+pub const INTERFACE_HEADER_VENDOR : u32 = sys::pack_chars(b'V', b'N', b'D', b'R');
+/// The header of interface tokens written from the recovery image.
+pub const INTERFACE_HEADER_RECOVERY : u32 = sys::pack_chars(b'R', b'E', b'C', b'O');
+
+/// The work source sent when we don't attribute the call to anybody else.
+const UNSET_WORK_SOURCE : i32 = -1;
+
+/// The layout of the interface token at the start of transactions. It grew
+/// with Android versions, and libbinder only understands its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceTokenFormat {
+    /// Up to Android 9 : the strict-mode policy, then the descriptor.
+    Legacy,
+    /// Android 10 : the uid the work is done for follows the strict-mode
+    /// policy.
+    WorkSource,
+    /// Android 11 and later : a header follows the work source, telling which
+    /// partition the sender was built for (see `INTERFACE_HEADER_SYSTEM`).
+    /// libbinder rejects tokens with another header than its own.
+    Header(u32)
+}
+
 pub trait Reader: Read + Seek {
     fn position(&self) -> u64;
 }
@@ -26,6 +56,20 @@ impl<T: AsRef<[u8]>> Reader for Cursor<T> {
     }
 }
 
+/// Stands in for local binders that are gone, so we can hand out weak
+/// references that never promote.
+struct GoneBinder;
+
+impl LocalBinder for GoneBinder {
+    fn get_interface_descriptor(&self) -> &str {
+        ""
+    }
+
+    fn on_transact(&self, _: u32, _: &mut Parcel, _: &mut OwnedParcel, _: u32) -> BinderResult<()> {
+        Err(BinderErrorKind::DeadObject.into())
+    }
+}
+
 // TODO: Might want to put some constraint on Parcel, such as implementing Debug
 pub trait Parcel {
     fn data(&mut self) -> &mut Reader;
@@ -64,34 +108,169 @@ pub trait Parcel {
         self.data().read_u32::<NativeEndian>()
     }
 
-    fn read_string16(&mut self) -> std::io::Result<String> {
-        let char_len = self.read_i32()?;
-        // TODO: Bounds checking
-        /*if char_len < 0 || char_len >= i32::max_value() {
-            // 
-            return;
-        */
-        println!("Allocating vec of size {} for string16", char_len);
-        let mut vec = Vec::with_capacity((char_len as usize + 1) * 2);
-        unsafe {
-            let capacity = vec.capacity();
-            self.read_buf(vec.get_unchecked_mut(..capacity))?;
-            vec.set_len(capacity);
+    fn read_i64(&mut self) -> std::io::Result<i64> {
+        self.data().read_i64::<NativeEndian>()
+    }
+
+    fn read_u64(&mut self) -> std::io::Result<u64> {
+        self.data().read_u64::<NativeEndian>()
+    }
+
+    fn read_f32(&mut self) -> std::io::Result<f32> {
+        self.data().read_f32::<NativeEndian>()
+    }
+
+    fn read_f64(&mut self) -> std::io::Result<f64> {
+        self.data().read_f64::<NativeEndian>()
+    }
+
+    /// Booleans are sent as an int32.
+    fn read_bool(&mut self) -> std::io::Result<bool> {
+        Ok(self.read_i32()? != 0)
+    }
+
+    /// Bytes are sent as an int32.
+    fn read_i8(&mut self) -> std::io::Result<i8> {
+        Ok(self.read_i32()? as i8)
+    }
+
+    /// Bytes are sent as an int32.
+    fn read_u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.read_i32()? as u8)
+    }
+
+    /// Read a UTF-16 code unit, the equivalent of a java `char`. Those are sent
+    /// as an int32.
+    fn read_char(&mut self) -> std::io::Result<u16> {
+        Ok(self.read_i32()? as u16)
+    }
+
+    /// The total size of the data in this parcel.
+    fn data_size(&mut self) -> usize {
+        self.as_data_slice_mut().len()
+    }
+
+    /// The current read position.
+    fn data_position(&mut self) -> usize {
+        self.data().position() as usize
+    }
+
+    /// Move the read position, e.g. to skip data we don't understand.
+    fn set_data_position(&mut self, pos: usize) -> std::io::Result<()> {
+        self.data().seek(SeekFrom::Start(pos as u64))?;
+        Ok(())
+    }
+
+    /// The amount of data left to read.
+    fn data_avail(&mut self) -> usize {
+        let pos = self.data_position();
+        self.data_size().saturating_sub(pos)
+    }
+
+    /// Read the length of a string or array. `None` stands for null, which is
+    /// sent as a length of -1. `unit_size` is the size of one element, and is
+    /// used to make sure the parcel is big enough.
+    fn read_nullable_len(&mut self, unit_size: usize) -> std::io::Result<Option<usize>> {
+        let len = self.read_i32()?;
+        if len == -1 {
+            return Ok(None)
         }
-        // TODO: Remove the last two bytes if they are == 0 (null character)
+        if len < 0 || (len as usize).saturating_mul(unit_size) > self.data_avail() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid length"))
+        }
+        Ok(Some(len as usize))
+    }
+
+    /// Read a UTF-16 string, or `None` if a null string was sent.
+    fn read_nullable_string16(&mut self) -> std::io::Result<Option<String>> {
+        let char_len = match self.read_nullable_len(2)? {
+            Some(len) => len,
+            None => return Ok(None)
+        };
+        let mut vec = vec![0; (char_len + 1) * 2];
+        self.read_buf(&mut vec)?;
+        // Drop the null terminator.
+        vec.truncate(char_len * 2);
         // TODO: Might want to return this ?
-        Ok(UTF_16LE_ENCODING.decode(vec.as_slice(), DecoderTrap::Replace)
-            .expect("Decoding from UTF16 should never fail"))
+        Ok(Some(UTF_16LE_ENCODING.decode(vec.as_slice(), DecoderTrap::Replace)
+            .expect("Decoding from UTF16 should never fail")))
+    }
+
+    /// Read a UTF-16 string. Null strings are rejected with `InvalidData`.
+    fn read_string16(&mut self) -> std::io::Result<String> {
+        self.read_nullable_string16()?
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Unexpected null string"))
+    }
+
+    /// Read a string sent as UTF-16, converting it to UTF-8. This is the same
+    /// as `read_string16`, and is what AIDL's `@utf8InCpp` strings use.
+    fn read_utf8_from_utf16(&mut self) -> std::io::Result<String> {
+        self.read_string16()
+    }
+
+    /// Nullable version of `read_utf8_from_utf16`.
+    fn read_nullable_utf8_from_utf16(&mut self) -> std::io::Result<Option<String>> {
+        self.read_nullable_string16()
+    }
+
+    /// Read a UTF-8 string, or `None` if a null string was sent.
+    fn read_nullable_string8(&mut self) -> std::io::Result<Option<String>> {
+        let len = match self.read_nullable_len(1)? {
+            Some(len) => len,
+            None => return Ok(None)
+        };
+        let mut vec = vec![0; len + 1];
+        self.read_buf(&mut vec)?;
+        // Drop the null terminator.
+        vec.truncate(len);
+        String::from_utf8(vec)
+            .map(Some)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /// Read a UTF-8 string. Null strings are rejected with `InvalidData`.
+    fn read_string8(&mut self) -> std::io::Result<String> {
+        self.read_nullable_string8()?
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Unexpected null string"))
+    }
+
+    /// Read the interface token written by `write_interface_token`, and check
+    /// that it matches `descriptor`. Fails with `BadType` otherwise.
+    ///
+    /// The sender may run another Android version than we do, so tokens in
+    /// every `InterfaceTokenFormat` are accepted.
+    fn enforce_interface(&mut self, descriptor: &str) -> BinderResult<()> {
+        let start = self.data_position();
+        // Newest first. A legacy token can't be read as a newer one, since the
+        // start of its descriptor is never a valid header.
+        let formats = [InterfaceTokenFormat::Header(INTERFACE_HEADER_SYSTEM), InterfaceTokenFormat::WorkSource, InterfaceTokenFormat::Legacy];
+        for &format in formats.iter() {
+            self.set_data_position(start)?;
+            if read_interface_descriptor(self, format).as_ref().map(|s| &s[..]) == Some(descriptor) {
+                return Ok(())
+            }
+        }
+        Err(BinderErrorKind::BadType.into())
     }
 
+    /// Read the exception header at the start of AIDL replies. Use
+    /// `Status::into_result` to turn it into an error.
+    fn read_exception(&mut self) -> BinderResult<Status> {
+        Status::read_from_parcel(self)
+    }
 
-    fn read_strong_binder(&mut self) -> BinderResult<Option<Rc<RefCell<Handle>>>> {
+    fn read_strong_binder(&mut self) -> BinderResult<Option<IBinder>> {
         match self.read_object(false) {
-            // TODO: This is a local object (I.E. belongs to my own address-space).
-            // I don't really need to support this for now, as I don't even
-            // support writing raw binders.
-            /*Ok(flat_binder_object { type_: sys::BinderType::Binder } @ flat) =>
-                flat.cookie,*/
+            // A NULL binder.
+            Ok(flat) if flat.type_ == sys::BinderType::Binder as u32 && flat.cookie == 0 =>
+                Ok(None),
+            // This is a local object (I.E. belongs to my own address-space).
+            // The kernel gives us back the cookie we sent it.
+            Ok(flat) if flat.type_ == sys::BinderType::Binder as u32 =>
+                match self.conn_mut().get_local_binder(flat.cookie) {
+                    Some(binder) => Ok(Some(IBinder::Local(binder))),
+                    None => Err(BinderErrorKind::BadType.into())
+                },
             // TODO: Some food for thought : I basically should never need to access
             // conn in an OwnedParcel, only in a BinderParcel. Because of this,
             // it might be worth moving read_strong_binder impl outside. Then,
@@ -102,18 +281,59 @@ pub trait Parcel {
             // stuff with ProcessState in libbinder. I might be able to side
             // step it as well, but it might not be so easy
             Ok(flat) if flat.type_ == sys::BinderType::Handle as u32 =>
-                Ok(Some(self.conn_mut().get_strong_proxy_for_handle(unsafe { flat.target.handle }))),
+                Ok(Some(IBinder::Remote(self.conn_mut().get_strong_proxy_for_handle(unsafe { flat.target.handle })))),
+            _ => Err(BinderErrorKind::BadType.into())
+        }
+    }
+
+    /// Read a weak binder. Strong binders are accepted as well, and are
+    /// downgraded.
+    fn read_weak_binder(&mut self) -> BinderResult<Option<WeakIBinder>> {
+        match self.read_object(false) {
+            Ok(flat) if (flat.type_ == sys::BinderType::Binder as u32 ||
+                         flat.type_ == sys::BinderType::WeakBinder as u32) && flat.cookie == 0 =>
+                Ok(None),
+            Ok(flat) if flat.type_ == sys::BinderType::Binder as u32 ||
+                        flat.type_ == sys::BinderType::WeakBinder as u32 =>
+                match self.conn_mut().get_local_binder(flat.cookie) {
+                    Some(binder) => Ok(Some(WeakIBinder::Local(Arc::downgrade(&binder)))),
+                    // We don't keep binders the kernel doesn't reference
+                    // anymore, so this one is gone.
+                    None => {
+                        let gone : sync::Weak<LocalBinder> = sync::Weak::<GoneBinder>::new();
+                        Ok(Some(WeakIBinder::Local(gone)))
+                    }
+                },
+            Ok(flat) if flat.type_ == sys::BinderType::Handle as u32 ||
+                        flat.type_ == sys::BinderType::WeakHandle as u32 => {
+                let handle = unsafe { flat.target.handle };
+                Ok(Some(WeakIBinder::Remote(Rc::new(WeakHandle::new(self.conn_mut().clone(), handle)))))
+            },
+            _ => Err(BinderErrorKind::BadType.into())
+        }
+    }
+
+    /// Read a file descriptor. The parcel keeps ownership of the descriptor it
+    /// contains, so the returned `File` wraps a duplicate of it.
+    fn read_file_descriptor(&mut self) -> BinderResult<File> {
+        match self.read_object(true) {
+            Ok(flat) if flat.type_ == sys::BinderType::Fd as u32 => {
+                let fd = dup_fd(unsafe { flat.target.handle } as RawFd)?;
+                Ok(unsafe { File::from_raw_fd(fd) })
+            },
             _ => Err(BinderErrorKind::BadType.into())
         }
     }
 
     // TODO: Private ?
     fn read_object(&mut self, null_metadata: bool) -> Result<flat_binder_object> {
+        let version = self.conn_mut().protocol_version();
         let mut buf = [0; size_of::<flat_binder_object>()];
+        let buf = &mut buf[..version.flat_binder_object_size()];
         let dpos = self.data().position();
-        self.read_buf(&mut buf)?;
-        let obj : sys::flat_binder_object = unsafe { std::mem::transmute(buf) };
-        if null_metadata && obj.cookie == 0 && unsafe { obj.target.binder == 0 } {
+        self.read_buf(buf)?;
+        let obj = version.read_flat_binder_object(&mut &buf[..])?;
+        if !null_metadata && obj.cookie == 0 && unsafe { obj.target.binder == 0 } {
             // When transferring a NULL object, we don't write it into the
             // object list, so we don't want to check for it when reading.
             Ok(obj)
@@ -129,6 +349,158 @@ pub trait Parcel {
     }
 }
 
+/// Read an interface token in `format`, returning its descriptor. Any of the
+/// known headers is accepted for `InterfaceTokenFormat::Header`.
+fn read_interface_descriptor<P: Parcel + ?Sized>(parcel: &mut P, format: InterfaceTokenFormat) -> Option<String> {
+    // TODO: strict-mode policy
+    parcel.read_i32().ok()?;
+    if format != InterfaceTokenFormat::Legacy {
+        // We don't attribute work to anybody else, so the work source is of
+        // no use to us.
+        parcel.read_i32().ok()?;
+    }
+    if let InterfaceTokenFormat::Header(_) = format {
+        let header = parcel.read_u32().ok()?;
+        if header != INTERFACE_HEADER_SYSTEM && header != INTERFACE_HEADER_VENDOR && header != INTERFACE_HEADER_RECOVERY {
+            return None
+        }
+    }
+    parcel.read_nullable_string16().ok()?
+}
+
+macro_rules! read_vector_methods {
+    ($($read:ident, $read_nullable:ident, $ty:ty, $size:expr, $elem:ident);*) => {
+        $(
+            fn $read_nullable(&mut self) -> std::io::Result<Option<Vec<$ty>>> {
+                self.read_nullable_vector_with($size, |p| p.$elem())
+            }
+
+            fn $read(&mut self) -> std::io::Result<Vec<$ty>> {
+                self.read_vector_with($size, |p| p.$elem())
+            }
+        )*
+    }
+}
+
+/// Array reading, using AIDL's encoding : an int32 length, -1 standing for a
+/// null array, followed by the elements.
+///
+/// Those methods live in their own trait because generic methods can't be
+/// called on a `Parcel` trait object. It is implemented for every `Parcel`,
+/// trait objects included.
+pub trait ParcelVectorExt: Parcel {
+    /// Read an array whose elements are read by `read`, or `None` if a null
+    /// array was sent. `min_elem_size` is the smallest size an element can
+    /// take in the parcel, and is used to reject lengths that can't possibly
+    /// fit in the remaining data before allocating anything.
+    fn read_nullable_vector_with<T, E, F>(&mut self, min_elem_size: usize, mut read: F) -> std::result::Result<Option<Vec<T>>, E>
+        where E: From<std::io::Error>,
+              F: FnMut(&mut Self) -> std::result::Result<T, E>
+    {
+        let len = match self.read_nullable_len(min_elem_size)? {
+            Some(len) => len,
+            None => return Ok(None)
+        };
+        let mut vec = Vec::with_capacity(len);
+        for _ in 0..len {
+            vec.push(read(&mut *self)?);
+        }
+        Ok(Some(vec))
+    }
+
+    /// Same as `read_nullable_vector_with`, but null arrays are rejected with
+    /// `InvalidData`.
+    fn read_vector_with<T, E, F>(&mut self, min_elem_size: usize, read: F) -> std::result::Result<Vec<T>, E>
+        where E: From<std::io::Error>,
+              F: FnMut(&mut Self) -> std::result::Result<T, E>
+    {
+        match self.read_nullable_vector_with(min_elem_size, read)? {
+            Some(vec) => Ok(vec),
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unexpected null array").into())
+        }
+    }
+
+    /// Read a byte array. Unlike other arrays, its elements are packed.
+    fn read_nullable_byte_vector(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let len = match self.read_nullable_len(1)? {
+            Some(len) => len,
+            None => return Ok(None)
+        };
+        let mut vec = vec![0; len];
+        self.read_buf(&mut vec)?;
+        Ok(Some(vec))
+    }
+
+    /// Read a byte array. Null arrays are rejected with `InvalidData`.
+    fn read_byte_vector(&mut self) -> std::io::Result<Vec<u8>> {
+        self.read_nullable_byte_vector()?
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Unexpected null array"))
+    }
+
+    read_vector_methods! {
+        read_bool_vector, read_nullable_bool_vector, bool, 4, read_bool;
+        read_char_vector, read_nullable_char_vector, u16, 4, read_char;
+        read_i32_vector, read_nullable_i32_vector, i32, 4, read_i32;
+        read_u32_vector, read_nullable_u32_vector, u32, 4, read_u32;
+        read_i64_vector, read_nullable_i64_vector, i64, 8, read_i64;
+        read_u64_vector, read_nullable_u64_vector, u64, 8, read_u64;
+        read_f32_vector, read_nullable_f32_vector, f32, 4, read_f32;
+        read_f64_vector, read_nullable_f64_vector, f64, 8, read_f64
+    }
+
+    /// Read an array of UTF-16 strings. Its elements may be null, as they are
+    /// in java's `String[]`.
+    fn read_nullable_string16_vector(&mut self) -> std::io::Result<Option<Vec<Option<String>>>> {
+        self.read_nullable_vector_with(4, |p| p.read_nullable_string16())
+    }
+
+    /// Read an array of UTF-16 strings. Null arrays are rejected with
+    /// `InvalidData`, but its elements may be null.
+    fn read_string16_vector(&mut self) -> std::io::Result<Vec<Option<String>>> {
+        self.read_vector_with(4, |p| p.read_nullable_string16())
+    }
+
+    /// Read an array of UTF-8 strings. Its elements may be null.
+    fn read_nullable_string8_vector(&mut self) -> std::io::Result<Option<Vec<Option<String>>>> {
+        self.read_nullable_vector_with(4, |p| p.read_nullable_string8())
+    }
+
+    /// Read an array of UTF-8 strings. Null arrays are rejected with
+    /// `InvalidData`, but its elements may be null.
+    fn read_string8_vector(&mut self) -> std::io::Result<Vec<Option<String>>> {
+        self.read_vector_with(4, |p| p.read_nullable_string8())
+    }
+
+    /// Read an array of strong binders. Its elements may be null.
+    fn read_nullable_strong_binder_vector(&mut self) -> BinderResult<Option<Vec<Option<IBinder>>>> {
+        let size = self.conn_mut().protocol_version().flat_binder_object_size();
+        self.read_nullable_vector_with(size, |p| p.read_strong_binder())
+    }
+
+    /// Read an array of strong binders. Null arrays are rejected with
+    /// `BadValue`.
+    fn read_strong_binder_vector(&mut self) -> BinderResult<Vec<Option<IBinder>>> {
+        let size = self.conn_mut().protocol_version().flat_binder_object_size();
+        self.read_vector_with(size, |p| p.read_strong_binder())
+    }
+}
+
+impl<P: Parcel + ?Sized> ParcelVectorExt for P {}
+
+macro_rules! write_vector_methods {
+    ($($write:ident, $write_nullable:ident, $ty:ty, $elem:ident);*) => {
+        $(
+            pub fn $write_nullable(&mut self, val: Option<&[$ty]>) {
+                self.write_nullable_vector_with(val, |p, elem| p.$elem(*elem))
+            }
+
+            pub fn $write(&mut self, val: &[$ty]) {
+                self.write_vector_with(val, |p, elem| p.$elem(*elem))
+            }
+        )*
+    }
+}
+
 // In the libbinder parcel, mOwner is a "free" function that is provided in the
 // case where the Parcel doesn't own the data buffer. When you try to grow data
 // that doesn't belong to you, there's a bit of very complicated logic in there.
@@ -179,10 +551,6 @@ impl OwnedParcel {
     }
 
     // TODO: Maybe I should let others access it ?
-    pub(crate) fn capacity(&self) -> usize {
-        self.data.get_ref().capacity()
-    }
-
     pub unsafe fn set_data_len(&mut self, size: usize) {
         self.data.get_mut().set_len(size)
     }
@@ -192,8 +560,22 @@ impl OwnedParcel {
     }
 
     pub fn clear(&mut self) {
+        close_file_descriptors(self.conn.protocol_version(), self.data.get_ref(), &self.objects, true);
         self.data.set_position(0);
         self.data.get_mut().clear();
+        self.objects.clear();
+        self.has_fds = Some(false);
+    }
+
+    /// Set whether file descriptors may be written to this parcel. Writing one
+    /// when they aren't allowed fails with `FdsNotAllowed`.
+    pub fn set_allow_fds(&mut self, allow_fds: bool) {
+        self.allow_fds = allow_fds;
+    }
+
+    /// Whether file descriptors may be written to this parcel.
+    pub fn allow_fds(&self) -> bool {
+        self.allow_fds
     }
 
     // TODO: I decided to make the write panic!() instead of return an error.
@@ -208,43 +590,293 @@ impl OwnedParcel {
         self.data.write_u32::<NativeEndian>(val).expect("Write bigger than usize");
     }
 
+    /// Write a `binder_uintptr_t`, which is 32-bit wide in version 7 of the
+    /// protocol.
     pub fn write_pointer(&mut self, val: sys::binder_uintptr_t) {
-        let buf : [u8; size_of::<sys::binder_uintptr_t>()] = unsafe { std::mem::transmute(val) };
-        self.data.write(&buf).expect("Write bigger than usize");
+        let version = self.conn.protocol_version();
+        version.write_pointer(&mut self.data, val).expect("Write bigger than usize");
     }
 
+    pub(crate) fn write_transaction_data(&mut self, txn: &sys::binder_transaction_data) {
+        let version = self.conn.protocol_version();
+        version.write_transaction_data(&mut self.data, txn).expect("Write bigger than usize");
+    }
+
+    /// Write the interface token starting a transaction to `interface`, in
+    /// the format returned by `ProcessState::interface_token_format`.
     pub fn write_interface_token(&mut self, interface: &str) {
+        let format = self.conn.process().interface_token_format();
+        self.write_interface_token_as(interface, format)
+    }
+
+    /// Write the interface token starting a transaction to `interface`, in
+    /// the given format.
+    pub fn write_interface_token_as(&mut self, interface: &str, format: InterfaceTokenFormat) {
         // TODO: strict-mode policy
         self.write_i32(0);
+        match format {
+            InterfaceTokenFormat::Legacy => (),
+            InterfaceTokenFormat::WorkSource => self.write_i32(UNSET_WORK_SOURCE),
+            InterfaceTokenFormat::Header(header) => {
+                self.write_i32(UNSET_WORK_SOURCE);
+                self.write_u32(header);
+            }
+        }
         self.write_string16(interface);
     }
 
+    /// Write the exception header of an AIDL reply.
+    pub fn write_exception(&mut self, status: &Status) {
+        status.write_to_parcel(self);
+    }
+
+    /// Write the exception header of a successful AIDL reply.
+    pub fn write_no_exception(&mut self) {
+        self.write_exception(&Status::ok());
+    }
+
+    pub fn write_i64(&mut self, val: i64) {
+        self.data.write_i64::<NativeEndian>(val).expect("Write bigger than usize");
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.data.write_u64::<NativeEndian>(val).expect("Write bigger than usize");
+    }
+
+    pub fn write_f32(&mut self, val: f32) {
+        self.data.write_f32::<NativeEndian>(val).expect("Write bigger than usize");
+    }
+
+    pub fn write_f64(&mut self, val: f64) {
+        self.data.write_f64::<NativeEndian>(val).expect("Write bigger than usize");
+    }
+
+    /// Booleans are sent as an int32.
+    pub fn write_bool(&mut self, val: bool) {
+        self.write_i32(if val { 1 } else { 0 });
+    }
+
+    /// Bytes are sent as an int32.
+    pub fn write_i8(&mut self, val: i8) {
+        self.write_i32(val as i32);
+    }
+
+    /// Bytes are sent as an int32.
+    pub fn write_u8(&mut self, val: u8) {
+        self.write_i32(val as i32);
+    }
+
+    /// Write a UTF-16 code unit, the equivalent of a java `char`. Those are
+    /// sent as an int32.
+    pub fn write_char(&mut self, val: u16) {
+        self.write_i32(val as i32);
+    }
+
+    /// Write a UTF-16 string. The length is sent in UTF-16 code units, and
+    /// the string is null-terminated.
     pub fn write_string16(&mut self, s: &str) {
-        self.write_i32(s.len() as i32);
         // TODO: Might want to return this ?
         let mut vec = UTF_16LE_ENCODING.encode(s, EncoderTrap::Replace)
             .expect("Encoding in UTF16 should never fail");
+        self.write_i32((vec.len() / 2) as i32);
         // Add \0 as char16_t
         vec.extend([0, 0].iter());
         self.write_buf(&vec)
     }
 
-    // TODO: This should take the eventual Binder enum
-    pub fn write_strong_binder(&mut self, handle: Option<Rc<RefCell<Handle>>>) {
+    /// Write a UTF-16 string. `None` is sent as a length of -1, which the
+    /// other side reads as a null string.
+    pub fn write_nullable_string16(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => self.write_string16(s),
+            None => self.write_i32(-1)
+        }
+    }
+
+    /// Write a UTF-8 string as UTF-16. This is the same as `write_string16`,
+    /// and is what AIDL's `@utf8InCpp` strings use.
+    pub fn write_utf8_as_utf16(&mut self, s: &str) {
+        self.write_string16(s)
+    }
+
+    /// Nullable version of `write_utf8_as_utf16`.
+    pub fn write_nullable_utf8_as_utf16(&mut self, s: Option<&str>) {
+        self.write_nullable_string16(s)
+    }
+
+    /// Write a null-terminated UTF-8 string.
+    pub fn write_string8(&mut self, s: &str) {
+        self.write_i32(s.len() as i32);
+        let mut vec = Vec::with_capacity(s.len() + 1);
+        vec.extend_from_slice(s.as_bytes());
+        vec.push(0);
+        self.write_buf(&vec)
+    }
+
+    /// Write a UTF-8 string. `None` is sent as a length of -1.
+    pub fn write_nullable_string8(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => self.write_string8(s),
+            None => self.write_i32(-1)
+        }
+    }
+
+    pub fn write_strong_binder(&mut self, binder: Option<IBinder>) {
         let mut obj : flat_binder_object = unsafe { std::mem::zeroed() };
-        // TODO: FLAT_BINDER_FLAGS
-        // obj.flags = 0x7f | FLAT_BINDER_FLAGS_ACCEPT_FDS;
-        if let Some(handle) = handle {
-            obj.type_ = sys::BinderType::Handle as u32;
-            obj.target.handle = handle.borrow().handle;
-        } else {
-            obj.type_ = sys::BinderType::Handle as u32;
+        obj.flags = 0x7f | sys::FlatBinderFlags::ACCEPT_FDS.bits();
+        match binder {
+            Some(IBinder::Local(ref binder)) => return self.write_local_binder(binder),
+            Some(IBinder::Remote(ref handle)) => {
+                obj.type_ = sys::BinderType::Handle as u32;
+                obj.target.handle = handle.borrow().handle;
+            },
+            // Like libbinder, NULL is written as a local binder without
+            // cookie.
+            None => obj.type_ = sys::BinderType::Binder as u32
         }
         self.write_object(obj, false).unwrap(); // TODO: Propagate error
     }
 
+    /// Write a binder living in the current process. The binder gets registered
+    /// with the connection, which will dispatch the transactions the other
+    /// processes send to it.
+    pub fn write_local_binder(&mut self, binder: &Arc<LocalBinder>) {
+        let ptr = self.conn.register_local_binder(binder);
+        let mut obj : flat_binder_object = unsafe { std::mem::zeroed() };
+        obj.type_ = sys::BinderType::Binder as u32;
+        obj.flags = 0x7f | sys::FlatBinderFlags::ACCEPT_FDS.bits();
+        obj.target.binder = ptr;
+        obj.cookie = ptr;
+        self.write_object(obj, false).unwrap(); // TODO: Propagate error
+    }
+
+    pub fn write_weak_binder(&mut self, binder: Option<WeakIBinder>) {
+        let mut obj : flat_binder_object = unsafe { std::mem::zeroed() };
+        obj.flags = 0x7f | sys::FlatBinderFlags::ACCEPT_FDS.bits();
+        match binder.as_ref() {
+            Some(&WeakIBinder::Local(ref binder)) => {
+                // If the binder is already gone, there's nothing left to
+                // reference. Send NULL, like libbinder does.
+                obj.type_ = sys::BinderType::Binder as u32;
+                if let Some(binder) = binder.upgrade() {
+                    let ptr = self.conn.register_local_binder(&binder);
+                    obj.type_ = sys::BinderType::WeakBinder as u32;
+                    obj.target.binder = ptr;
+                    obj.cookie = ptr;
+                }
+            },
+            Some(&WeakIBinder::Remote(ref handle)) => {
+                obj.type_ = sys::BinderType::WeakHandle as u32;
+                obj.target.handle = handle.handle;
+            },
+            None => obj.type_ = sys::BinderType::Binder as u32
+        }
+        self.write_object(obj, false).unwrap(); // TODO: Propagate error
+    }
+
+    /// Write a file descriptor, taking ownership of it. It is closed when the
+    /// parcel is dropped or cleared. The kernel installs a duplicate of it in
+    /// the receiving process.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FdsNotAllowed` if this parcel doesn't accept file descriptors,
+    /// in which case the fd is closed right away.
+    pub fn write_file_descriptor<F: IntoRawFd>(&mut self, fd: F) -> BinderResult<()> {
+        let fd = fd.into_raw_fd();
+        let mut obj : flat_binder_object = unsafe { std::mem::zeroed() };
+        obj.type_ = sys::BinderType::Fd as u32;
+        obj.flags = 0x7f | sys::FlatBinderFlags::ACCEPT_FDS.bits();
+        obj.target.handle = fd as u32;
+        // Like libbinder, a non-zero cookie marks the fd as owned by the
+        // parcel.
+        obj.cookie = 1;
+        // The fd might be 0, so the object has to be recorded even though its
+        // target is null.
+        let res = self.write_object(obj, true);
+        if res.is_err() {
+            let _ = unsafe { libc::close(fd) };
+        }
+        res
+    }
+
+    /// Write a duplicate of `fd`. The caller keeps ownership of the original.
+    pub fn write_dup_file_descriptor<F: AsRawFd>(&mut self, fd: &F) -> BinderResult<()> {
+        let dup = dup_fd(fd.as_raw_fd())?;
+        self.write_file_descriptor(unsafe { File::from_raw_fd(dup) })
+    }
+
+    /// Write an array, writing its elements with `write`. `None` is sent as a
+    /// length of -1, which the other side reads as a null array.
+    pub fn write_nullable_vector_with<T, F>(&mut self, val: Option<&[T]>, write: F)
+        where F: FnMut(&mut OwnedParcel, &T)
+    {
+        match val {
+            Some(val) => self.write_vector_with(val, write),
+            None => self.write_i32(-1)
+        }
+    }
+
+    /// Write an array, writing its elements with `write`.
+    pub fn write_vector_with<T, F>(&mut self, val: &[T], mut write: F)
+        where F: FnMut(&mut OwnedParcel, &T)
+    {
+        self.write_i32(val.len() as i32);
+        for elem in val {
+            write(&mut *self, elem);
+        }
+    }
+
+    /// Write a byte array. Unlike other arrays, its elements are packed.
+    pub fn write_nullable_byte_vector(&mut self, val: Option<&[u8]>) {
+        match val {
+            Some(val) => self.write_byte_vector(val),
+            None => self.write_i32(-1)
+        }
+    }
+
+    /// Write a byte array. Unlike other arrays, its elements are packed.
+    pub fn write_byte_vector(&mut self, val: &[u8]) {
+        self.write_i32(val.len() as i32);
+        self.write_buf(val);
+    }
+
+    write_vector_methods! {
+        write_bool_vector, write_nullable_bool_vector, bool, write_bool;
+        write_char_vector, write_nullable_char_vector, u16, write_char;
+        write_i32_vector, write_nullable_i32_vector, i32, write_i32;
+        write_u32_vector, write_nullable_u32_vector, u32, write_u32;
+        write_i64_vector, write_nullable_i64_vector, i64, write_i64;
+        write_u64_vector, write_nullable_u64_vector, u64, write_u64;
+        write_f32_vector, write_nullable_f32_vector, f32, write_f32;
+        write_f64_vector, write_nullable_f64_vector, f64, write_f64
+    }
+
+    pub fn write_nullable_string16_vector<S: AsRef<str>>(&mut self, val: Option<&[S]>) {
+        self.write_nullable_vector_with(val, |p, s| p.write_string16(s.as_ref()))
+    }
+
+    pub fn write_string16_vector<S: AsRef<str>>(&mut self, val: &[S]) {
+        self.write_vector_with(val, |p, s| p.write_string16(s.as_ref()))
+    }
+
+    pub fn write_nullable_string8_vector<S: AsRef<str>>(&mut self, val: Option<&[S]>) {
+        self.write_nullable_vector_with(val, |p, s| p.write_string8(s.as_ref()))
+    }
+
+    pub fn write_string8_vector<S: AsRef<str>>(&mut self, val: &[S]) {
+        self.write_vector_with(val, |p, s| p.write_string8(s.as_ref()))
+    }
+
+    pub fn write_nullable_strong_binder_vector(&mut self, val: Option<&[Option<IBinder>]>) {
+        self.write_nullable_vector_with(val, |p, binder| p.write_strong_binder(binder.clone()))
+    }
+
+    pub fn write_strong_binder_vector(&mut self, val: &[Option<IBinder>]) {
+        self.write_vector_with(val, |p, binder| p.write_strong_binder(binder.clone()))
+    }
+
     fn write_object(&mut self, val: flat_binder_object, null_metadata: bool) -> BinderResult<()> {
-        //self.write_type(val)
         if val.type_ == sys::BinderType::Fd as u32 {
             if !self.allow_fds {
                 return Err(BinderErrorKind::FdsNotAllowed.into())
@@ -252,8 +884,11 @@ impl OwnedParcel {
                 self.has_fds = Some(true);
             }
         }
+        let pos = self.data.position() as usize;
+        let version = self.conn.protocol_version();
+        version.write_flat_binder_object(&mut self.data, &val).expect("Write bigger than usize");
         if null_metadata || unsafe { val.target.binder } != 0 {
-            self.objects.push(self.data.position() as usize);
+            self.objects.push(pos);
             // TODO: acquire_object
         }
         Ok(())
@@ -273,6 +908,12 @@ impl OwnedParcel {
     // TODO: readValue/writeValue
 }
 
+impl Drop for OwnedParcel {
+    fn drop(&mut self) {
+        close_file_descriptors(self.conn.protocol_version(), self.data.get_ref(), &self.objects, true);
+    }
+}
+
 impl Parcel for OwnedParcel {
     fn data(&mut self) -> &mut Reader {
         &mut self.data
@@ -299,11 +940,19 @@ impl Parcel for OwnedParcel {
 }
 
 // TODO: Is it *const or *mut ???
-pub unsafe fn create_binder_parcel<'a>(binder: BinderConnection, data: *mut u8, data_len: usize, offsets: *mut usize, offset_len: usize) -> (BinderParcel<'a>) {
-    println!("Creating binderparcel with data_len {}", data_len);
+/// Wrap a buffer received from the driver. The offsets are an array of
+/// `binder_size_t`, `offsets_size` bytes long.
+pub unsafe fn create_binder_parcel<'a>(binder: BinderConnection, data: *mut u8, data_len: usize, offsets: *const u8, offsets_size: usize) -> (BinderParcel<'a>) {
+    trace!("Creating binderparcel with data_len {}", data_len);
+    let version = binder.protocol_version();
+    let mut raw_offsets = std::slice::from_raw_parts(offsets, offsets_size);
+    let mut decoded = Vec::with_capacity(offsets_size / version.pointer_size());
+    while let Ok(pos) = version.read_pointer(&mut raw_offsets) {
+        decoded.push(pos as usize);
+    }
     BinderParcel {
         data: Cursor::new(std::slice::from_raw_parts_mut(data, data_len)),
-        offsets: std::slice::from_raw_parts_mut(offsets, offset_len),
+        offsets: decoded,
         conn: binder
     }
 }
@@ -311,7 +960,7 @@ pub unsafe fn create_binder_parcel<'a>(binder: BinderConnection, data: *mut u8,
 #[derive(Debug)]
 pub struct BinderParcel<'a> {
     data: Cursor<&'a mut [u8]>,
-    offsets: &'a mut [usize],
+    offsets: Vec<usize>,
     conn: BinderConnection
 }
 
@@ -342,7 +991,37 @@ impl<'a> Parcel for BinderParcel<'a> {
 
 impl<'a> Drop for BinderParcel<'a> {
     fn drop(&mut self) {
-        let _ = self.conn.free_buffer(self.data.get_mut().as_mut_ptr());
+        // The kernel installed the fds we received in our process, and nobody
+        // else is going to close them.
+        close_file_descriptors(self.conn.protocol_version(), self.data.get_ref(), &self.offsets, false);
+        self.conn.free_buffer(self.data.get_mut().as_mut_ptr());
     }
 }
 
+/// Duplicate `fd`, with close-on-exec set like libbinder does.
+fn dup_fd(fd: RawFd) -> BinderResult<RawFd> {
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(libc::EBADF);
+        Err(BinderError::from(-errno))
+    } else {
+        Ok(dup)
+    }
+}
+
+/// Close the file descriptors found in the objects of a parcel. If
+/// `owned_only` is set, only those the parcel took ownership of are closed.
+fn close_file_descriptors(version: sys::ProtocolVersion, data: &[u8], objects: &[usize], owned_only: bool) {
+    for &pos in objects {
+        if pos + version.flat_binder_object_size() > data.len() {
+            continue;
+        }
+        let obj = match version.read_flat_binder_object(&mut &data[pos..]) {
+            Ok(obj) => obj,
+            Err(_) => continue
+        };
+        if obj.type_ == sys::BinderType::Fd as u32 && (!owned_only || obj.cookie != 0) {
+            let _ = unsafe { libc::close(obj.target.handle as RawFd) };
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//!
+//! Parcelable
+//!
+//! Types that know how to write themselves to a `Parcel`, and read themselves
+//! back. This is the equivalent of android's `Parcelable` interface.
+//!
+//! Structs and C-like enums can get an implementation generated with
+//! `#[derive(Parcelable)]`, from the `binder-derive` crate. Structs use the
+//! framing of AIDL's structured parcelables :
+//!
+//! - The fields are preceded by the total size of the parcelable, header
+//!   included. This allows the reader to skip trailing fields it doesn't know
+//!   about, and gives the default value to fields the writer didn't know
+//!   about.
+//! - When used as a field, argument or array element, a parcelable is
+//!   preceded by an int32 marker, `0` meaning null and `1` meaning present.
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate binder_derive;
+//!
+//! #[derive(Debug, Default, Parcelable)]
+//! struct Rect {
+//!     left: i32,
+//!     top: i32,
+//!     right: i32,
+//!     bottom: i32
+//! }
+//! ```
+//!
+
+use std;
+
+use error::{BinderResult, BinderErrorKind};
+use parcel::{Parcel, ParcelVectorExt, OwnedParcel};
+use IBinder;
+
+/// A type that can be sent over binder.
+///
+/// Only `write_to_parcel` and `read_from_parcel` have to be implemented. The
+/// other methods describe how the type is written when it is nested in
+/// another value, and have sensible defaults for structured parcelables.
+pub trait Parcelable: Sized {
+    /// Write the contents of this value.
+    fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()>;
+
+    /// Read a value written by `write_to_parcel`.
+    fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self>;
+
+    /// Write this value as a field, argument or array element. By default,
+    /// it is preceded by a non-null marker.
+    fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_i32(1);
+        self.write_to_parcel(parcel)
+    }
+
+    /// Read a value written by `write_value`. A null value is rejected with
+    /// `UnexpectedNull`.
+    fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        match Self::read_nullable_value(parcel)? {
+            Some(val) => Ok(val),
+            None => Err(BinderErrorKind::UnexpectedNull.into())
+        }
+    }
+
+    /// Write a value that might be null. By default, null is written as a
+    /// `0` marker.
+    fn write_nullable_value(val: Option<&Self>, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        match val {
+            Some(val) => val.write_value(parcel),
+            None => {
+                parcel.write_i32(0);
+                Ok(())
+            }
+        }
+    }
+
+    /// Read a value written by `write_nullable_value`.
+    fn read_nullable_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Self>> {
+        if parcel.read_i32()? == 0 {
+            Ok(None)
+        } else {
+            Self::read_from_parcel(parcel).map(Some)
+        }
+    }
+
+    /// Write an array of this type. `None` is written as a null array.
+    fn write_slice(val: Option<&[Self]>, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        match val {
+            Some(val) => {
+                parcel.write_i32(val.len() as i32);
+                for elem in val {
+                    elem.write_value(parcel)?;
+                }
+                Ok(())
+            },
+            None => {
+                parcel.write_i32(-1);
+                Ok(())
+            }
+        }
+    }
+
+    /// Read an array written by `write_slice`.
+    fn read_vec<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Vec<Self>>> {
+        parcel.read_nullable_vector_with(4, |p| Self::read_value(p))
+    }
+}
+
+/// The longest out array AIDL stubs allocate for their caller. Replies have to
+/// fit in the caller's buffer space, which is about 1MB by default, so callers
+/// can't get a longer one back anyway.
+pub const MAX_OUT_ARRAY_LEN : usize = 1 << 20;
+
+/// Write the fields of a structured parcelable, preceded by their size. Used
+/// by `#[derive(Parcelable)]`.
+pub fn write_sized<F>(parcel: &mut OwnedParcel, write: F) -> BinderResult<()>
+    where F: FnOnce(&mut OwnedParcel) -> BinderResult<()>
+{
+    let start = parcel.data_position();
+    // Placeholder, filled once we know the size.
+    parcel.write_i32(0);
+    write(parcel)?;
+    let end = parcel.data_position();
+    parcel.set_position(start);
+    parcel.write_i32((end - start) as i32);
+    parcel.set_position(end);
+    Ok(())
+}
+
+/// Read the size header of a structured parcelable, returning the position
+/// where it ends. Fields may only be read up to there, and the reader should
+/// then move to it to skip the fields it doesn't know about. Used by
+/// `#[derive(Parcelable)]`.
+pub fn read_size_header<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<usize> {
+    let start = parcel.data_position();
+    let size = parcel.read_i32()?;
+    // The size includes the header itself.
+    if size < 4 || size as usize > parcel.data_size() - start {
+        return Err(BinderErrorKind::BadValue.into())
+    }
+    Ok(start + size as usize)
+}
+
+// Primitives are written as is, without any marker.
+macro_rules! primitive_parcelable {
+    ($($ty:ty, $write:ident, $read:ident);*) => {
+        $(
+            impl Parcelable for $ty {
+                fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+                    parcel.$write(*self);
+                    Ok(())
+                }
+
+                fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+                    Ok(parcel.$read()?)
+                }
+
+                fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+                    self.write_to_parcel(parcel)
+                }
+
+                fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+                    Self::read_from_parcel(parcel)
+                }
+            }
+        )*
+    }
+}
+
+primitive_parcelable! {
+    bool, write_bool, read_bool;
+    i8, write_i8, read_i8;
+    u16, write_char, read_char;
+    i32, write_i32, read_i32;
+    u32, write_u32, read_u32;
+    i64, write_i64, read_i64;
+    u64, write_u64, read_u64;
+    f32, write_f32, read_f32;
+    f64, write_f64, read_f64
+}
+
+impl Parcelable for u8 {
+    fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_u8(*self);
+        Ok(())
+    }
+
+    fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        Ok(parcel.read_u8()?)
+    }
+
+    fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        self.write_to_parcel(parcel)
+    }
+
+    fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        Self::read_from_parcel(parcel)
+    }
+
+    // Byte arrays are packed.
+    fn write_slice(val: Option<&[Self]>, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_nullable_byte_vector(val);
+        Ok(())
+    }
+
+    fn read_vec<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Vec<Self>>> {
+        Ok(parcel.read_nullable_byte_vector()?)
+    }
+}
+
+// Strings are written as String16, with null being a length of -1.
+impl Parcelable for String {
+    fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_string16(self);
+        Ok(())
+    }
+
+    fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        match parcel.read_nullable_string16()? {
+            Some(s) => Ok(s),
+            None => Err(BinderErrorKind::UnexpectedNull.into())
+        }
+    }
+
+    fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        self.write_to_parcel(parcel)
+    }
+
+    fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        Self::read_from_parcel(parcel)
+    }
+
+    fn write_nullable_value(val: Option<&Self>, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_nullable_string16(val.map(|s| s.as_str()));
+        Ok(())
+    }
+
+    fn read_nullable_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Self>> {
+        Ok(parcel.read_nullable_string16()?)
+    }
+}
+
+// Binders are written as flat binder objects, which have their own way of
+// representing null.
+impl Parcelable for IBinder {
+    fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_strong_binder(Some(self.clone()));
+        Ok(())
+    }
+
+    fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        match parcel.read_strong_binder()? {
+            Some(binder) => Ok(binder),
+            None => Err(BinderErrorKind::UnexpectedNull.into())
+        }
+    }
+
+    fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        self.write_to_parcel(parcel)
+    }
+
+    fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        Self::read_from_parcel(parcel)
+    }
+
+    fn write_nullable_value(val: Option<&Self>, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        parcel.write_strong_binder(val.cloned());
+        Ok(())
+    }
+
+    fn read_nullable_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Self>> {
+        parcel.read_strong_binder()
+    }
+
+    fn read_vec<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Vec<Self>>> {
+        parcel.read_nullable_vector_with(std::mem::size_of::<::sys::flat_binder_object>(), |p| Self::read_value(p))
+    }
+}
+
+// Arrays are written with their length first, null being a length of -1.
+impl<T: Parcelable> Parcelable for Vec<T> {
+    fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        T::write_slice(Some(self.as_slice()), parcel)
+    }
+
+    fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        match T::read_vec(parcel)? {
+            Some(vec) => Ok(vec),
+            None => Err(BinderErrorKind::UnexpectedNull.into())
+        }
+    }
+
+    fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        self.write_to_parcel(parcel)
+    }
+
+    fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        Self::read_from_parcel(parcel)
+    }
+
+    fn write_nullable_value(val: Option<&Self>, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        T::write_slice(val.map(|v| v.as_slice()), parcel)
+    }
+
+    fn read_nullable_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Option<Self>> {
+        T::read_vec(parcel)
+    }
+}
+
+// Nullable values are written according to the inner type.
+impl<T: Parcelable> Parcelable for Option<T> {
+    fn write_to_parcel(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        T::write_nullable_value(self.as_ref(), parcel)
+    }
+
+    fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        T::read_nullable_value(parcel)
+    }
+
+    fn write_value(&self, parcel: &mut OwnedParcel) -> BinderResult<()> {
+        self.write_to_parcel(parcel)
+    }
+
+    fn read_value<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Self> {
+        Self::read_from_parcel(parcel)
+    }
+}
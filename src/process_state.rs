@@ -0,0 +1,433 @@
+//!
+//! Process State
+//!
+//! The state shared by every thread talking to the same binder driver
+//! instance. This is the equivalent of libbinder's ProcessState : it owns the
+//! file descriptor and the memory mapping, knows about the local binders we
+//! sent to other processes, and manages the binder thread pool.
+//!
+//! Each thread talks to the driver through its own `BinderConnection`, created
+//! from the shared `ProcessState` with `BinderConnection::new`.
+//!
+//! ```no_run
+//! # use binder::BinderConnection;
+//! # use binder::process_state::ProcessState;
+//! let conn = BinderConnection::open().unwrap();
+//! let process = conn.process();
+//! process.set_max_threads(4).unwrap();
+//! ProcessState::start_thread_pool(&process);
+//! ```
+//!
+
+use std;
+use std::collections::HashMap;
+use std::fmt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+use nix;
+use libc;
+
+use {BinderConnection, DeathRecipient, Result, ErrorKind};
+use local_binder::{self, LocalBinder};
+use parcel::InterfaceTokenFormat;
+use sys;
+
+/// The device opened by `ProcessState::open`.
+pub const DEFAULT_BINDER_DEVICE : &str = "/dev/binder";
+
+/// The default size of the memory mapping the driver copies incoming
+/// transactions into. Same as libbinder.
+pub const BINDER_VM_SIZE : usize = (1024 * 1024) - (4096 * 2);
+
+/// The maximum amount of threads the driver may ask us to spawn, set when the
+/// device is opened. Same as libbinder.
+pub const DEFAULT_MAX_THREADS : u32 = 15;
+
+/// The state of the current process, shared by all the threads talking to the
+/// driver.
+pub struct ProcessState {
+    fd: RawFd,
+    vm_start: *mut nix::libc::c_void,
+    vm_size: usize,
+    /// The protocol version implemented by the driver.
+    version: sys::ProtocolVersion,
+    /// The local binders we sent to the kernel, indexed by their ptr. The
+    /// kernel will use this ptr as the target of the transactions sent to
+    /// them.
+    local_objects: Mutex<HashMap<sys::binder_uintptr_t, LocalObject>>,
+    /// The local binder we registered as the context manager, if any. Kept
+    /// alive for the lifetime of the process, like libbinder does.
+    context_object: Mutex<Option<Arc<LocalBinder>>>,
+    /// The registered death recipients, indexed by the cookie we sent to the
+    /// kernel along with their handle.
+    death_recipients: Mutex<HashMap<sys::binder_uintptr_t, (u32, Arc<DeathRecipient>)>>,
+    next_death_cookie: AtomicUsize,
+    /// The value given to `binder_set_max_threads`.
+    max_threads: AtomicUsize,
+    /// The amount of pooled threads currently running, excluding the main one.
+    running_threads: AtomicUsize,
+    /// Used to give each pooled thread a different name.
+    thread_counter: AtomicUsize,
+    thread_pool_started: AtomicBool,
+    /// How we write interface tokens, which depends on the Android version
+    /// of the processes we talk to.
+    interface_token_format: Mutex<InterfaceTokenFormat>
+}
+
+/// An entry of the local object table.
+struct LocalObject {
+    binder: Arc<LocalBinder>,
+    /// The strong references the driver holds on this object.
+    strong: usize,
+    /// The weak references the driver holds on this object.
+    weak: usize
+}
+
+/// Add `binder` to the object table if it isn't there yet, returning its ptr.
+fn insert_local_object(objects: &mut HashMap<sys::binder_uintptr_t, LocalObject>, binder: &Arc<LocalBinder>) -> sys::binder_uintptr_t {
+    let ptr = local_binder::binder_ptr(binder);
+    objects.entry(ptr).or_insert_with(|| LocalObject {
+        binder: binder.clone(),
+        strong: 0,
+        weak: 0
+    });
+    ptr
+}
+
+// The raw vm_start pointer is only ever handed to the kernel, and everything
+// else is behind a Mutex or an atomic.
+unsafe impl Send for ProcessState {}
+unsafe impl Sync for ProcessState {}
+
+// LocalBinder isn't Debug, so we have to write this by hand.
+impl fmt::Debug for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProcessState")
+            .field("fd", &self.fd)
+            .field("version", &self.version)
+            .field("vm_start", &self.vm_start)
+            .field("vm_size", &self.vm_size)
+            .field("local_objects", &self.local_objects.lock().unwrap().keys().collect::<Vec<_>>())
+            .field("max_threads", &self.max_threads)
+            .field("running_threads", &self.running_threads)
+            .finish()
+    }
+}
+
+impl Drop for ProcessState {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.fd);
+        let _ = unsafe { nix::sys::mman::munmap(self.vm_start, self.vm_size) };
+    }
+}
+
+impl ProcessState {
+    ///
+    /// Open the Binder Kernel Interface, through `/dev/binder`, with the
+    /// default VM size. Use `open_device` to talk to another binder device.
+    ///
+    /// # Errors
+    ///
+    /// This function will return :
+    ///
+    /// - `WrongProtocolVersion` if the kernel binder driver implements an
+    ///   unknown protocol version, or version 7 on a 64-bit process (the
+    ///   32-bit protocol can't hold our pointers)
+    /// - `Io` if there is an error opening the connection to the driver
+    /// - `Nix` if there is an error mmapping the Binder VM
+    pub fn open() -> Result<Arc<ProcessState>> {
+        ProcessState::open_device(DEFAULT_BINDER_DEVICE, BINDER_VM_SIZE)
+    }
+
+    /// Open the binder device node at `device`, such as `/dev/hwbinder`,
+    /// `/dev/vndbinder` or a node of a mounted binderfs instance, and map
+    /// `vm_size` bytes of its buffer space.
+    ///
+    /// Like libbinder, the driver is allowed to ask for up to
+    /// `DEFAULT_MAX_THREADS` threads. See `set_max_threads`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `open`.
+    pub fn open_device<P: AsRef<Path>>(device: P, vm_size: usize) -> Result<Arc<ProcessState>> {
+        use nix::sys::mman::*;
+        use std::os::unix::io::IntoRawFd;
+
+        let fd = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(device)?.into_raw_fd();
+
+        let mut vers : sys::binder_version = unsafe { std::mem::zeroed() };
+        unsafe { sys::binder_version(fd, &mut vers)?; }
+        let version = match sys::ProtocolVersion::from_i32(vers.protocol_version) {
+            Some(version) if version.pointer_size() >= std::mem::size_of::<usize>() => version,
+            _ => {
+                error!("Binder driver protocol {} is not supported by this process!", vers.protocol_version);
+                let _ = nix::unistd::close(fd);
+                return Err(ErrorKind::WrongProtocolVersion.into())
+            }
+        };
+        debug!("Using binder protocol version {}", version.as_i32());
+        // So, I need to make sure my theory is right, but I *think* binder
+        // returns pointer to stuff in this zone when it needs to. This means
+        // I need to find those pointer, and bind their lifetime to
+        // BinderConnection...
+        let map = match unsafe { mmap(std::ptr::null_mut(), vm_size, PROT_READ, MAP_PRIVATE | MAP_NORESERVE, fd, 0) } {
+            Ok(map) => map,
+            Err(err) => {
+                let _ = nix::unistd::close(fd);
+                return Err(err.into())
+            }
+        };
+        let process = Arc::new(ProcessState {
+            fd: fd,
+            vm_start: map,
+            vm_size: vm_size,
+            version: version,
+            local_objects: Mutex::new(HashMap::default()),
+            context_object: Mutex::new(None),
+            death_recipients: Mutex::new(HashMap::default()),
+            next_death_cookie: AtomicUsize::new(1),
+            max_threads: AtomicUsize::new(0),
+            running_threads: AtomicUsize::new(0),
+            thread_counter: AtomicUsize::new(0),
+            thread_pool_started: AtomicBool::new(false),
+            interface_token_format: Mutex::new(InterfaceTokenFormat::Legacy)
+        });
+        if let Err(err) = process.set_max_threads(DEFAULT_MAX_THREADS) {
+            warn!("Failed to set the maximum amount of binder threads: {}", err);
+        }
+        Ok(process)
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// The protocol version implemented by the driver, which decides the
+    /// layout of the structures we exchange with it.
+    pub fn protocol_version(&self) -> sys::ProtocolVersion {
+        self.version
+    }
+
+    /// Register a local binder in the object table, so that transactions sent
+    /// to it can be routed back. Returns the ptr identifying it to the kernel.
+    pub(crate) fn register_local_binder(&self, binder: &Arc<LocalBinder>) -> sys::binder_uintptr_t {
+        insert_local_object(&mut self.local_objects.lock().unwrap(), binder)
+    }
+
+    /// Get the local binder registered with the given ptr, if any.
+    ///
+    /// When we became the context manager with the legacy ioctl, the
+    /// transactions sent to handle 0 have a null ptr.
+    pub(crate) fn get_local_binder(&self, ptr: sys::binder_uintptr_t) -> Option<Arc<LocalBinder>> {
+        if ptr == 0 {
+            return self.context_object.lock().unwrap().clone()
+        }
+        self.local_objects.lock().unwrap().get(&ptr).map(|obj| obj.binder.clone())
+    }
+
+    /// Register `binder` as the context manager, making it handle 0 for every
+    /// process using the same binder device. Transactions sent to it are
+    /// dispatched like those sent to any other local binder, so a thread has to
+    /// be looping on the connection (see `start_thread_pool`).
+    ///
+    /// Only one process may be the context manager of a device. On Android,
+    /// that's the service manager.
+    ///
+    /// # Errors
+    ///
+    /// - `Binder(AlreadyExists)` if we already are the context manager
+    /// - `Binder(UnknownError(-EBUSY))` if another process is the context
+    ///   manager
+    /// - `Binder(PermissionDenied)` if the security policy forbids it
+    pub fn become_context_manager(&self, binder: Arc<LocalBinder>) -> Result<()> {
+        let mut context_object = self.context_object.lock().unwrap();
+        if context_object.is_some() {
+            return Err(::BinderError::from_kind(::BinderErrorKind::AlreadyExists).into())
+        }
+
+        let ptr = local_binder::binder_ptr(&binder);
+        let mut obj : sys::flat_binder_object = unsafe { std::mem::zeroed() };
+        obj.type_ = sys::BinderType::Binder as u32;
+        obj.flags = sys::FlatBinderFlags::ACCEPT_FDS.bits();
+        obj.target.binder = ptr;
+        obj.cookie = ptr;
+        // Hold the object table until the binder is registered, so looping
+        // threads don't see the driver take references to an unknown binder.
+        let mut objects = self.local_objects.lock().unwrap();
+        let res = match unsafe { sys::set_context_mgr_ext(self.fd, self.version, &obj) } {
+            Ok(_) => {
+                insert_local_object(&mut objects, &binder);
+                Ok(())
+            },
+            Err(nix::Error::Sys(nix::errno::Errno::EINVAL)) => {
+                // Kernels older than 4.14 don't know about the extended
+                // ioctl. Transactions will have a null target, and the
+                // driver never references the binder by its ptr.
+                debug!("BINDER_SET_CONTEXT_MGR_EXT unsupported, falling back to BINDER_SET_CONTEXT_MGR");
+                unsafe { sys::binder_set_context_mgr(self.fd, &0) }.map(|_| ())
+            },
+            Err(err) => Err(err)
+        };
+        if let Err(err) = res {
+            return Err(::BinderError::from(err).into())
+        }
+        *context_object = Some(binder);
+        Ok(())
+    }
+
+    /// The driver took a reference to one of our local binders.
+    pub(crate) fn inc_local_refs(&self, ptr: sys::binder_uintptr_t, strong: bool) {
+        match self.local_objects.lock().unwrap().get_mut(&ptr) {
+            Some(obj) => if strong { obj.strong += 1 } else { obj.weak += 1 },
+            None => warn!("Driver took a reference to unknown local binder {:#x}", ptr)
+        }
+    }
+
+    /// The driver asks whether it may take a strong reference to one of our
+    /// local binders. This is only possible if the binder is still in the
+    /// object table.
+    pub(crate) fn attempt_inc_local_strong(&self, ptr: sys::binder_uintptr_t) -> bool {
+        match self.local_objects.lock().unwrap().get_mut(&ptr) {
+            Some(obj) => {
+                obj.strong += 1;
+                true
+            },
+            None => false
+        }
+    }
+
+    /// The driver dropped a reference to one of our local binders. Once it
+    /// doesn't hold any, the binder is removed from the object table.
+    pub(crate) fn dec_local_refs(&self, ptr: sys::binder_uintptr_t, strong: bool) {
+        let mut objects = self.local_objects.lock().unwrap();
+        let unused = match objects.get_mut(&ptr) {
+            Some(obj) => {
+                if strong {
+                    obj.strong = obj.strong.saturating_sub(1);
+                } else {
+                    obj.weak = obj.weak.saturating_sub(1);
+                }
+                obj.strong == 0 && obj.weak == 0
+            },
+            None => {
+                warn!("Driver dropped a reference to unknown local binder {:#x}", ptr);
+                false
+            }
+        };
+        if unused {
+            objects.remove(&ptr);
+        }
+    }
+
+    /// Register a death recipient for `handle`, returning the cookie to give to
+    /// the kernel.
+    pub(crate) fn add_death_recipient(&self, handle: u32, recipient: Arc<DeathRecipient>) -> sys::binder_uintptr_t {
+        let cookie = self.next_death_cookie.fetch_add(1, Ordering::SeqCst) as sys::binder_uintptr_t;
+        self.death_recipients.lock().unwrap().insert(cookie, (handle, recipient));
+        cookie
+    }
+
+    /// Check whether `cookie` was given out for `recipient`.
+    pub(crate) fn death_recipient_is(&self, cookie: sys::binder_uintptr_t, recipient: &Arc<DeathRecipient>) -> bool {
+        match self.death_recipients.lock().unwrap().get(&cookie) {
+            Some(&(_, ref registered)) =>
+                &**registered as *const DeathRecipient as *const u8 == &**recipient as *const DeathRecipient as *const u8,
+            None => false
+        }
+    }
+
+    pub(crate) fn remove_death_recipient(&self, cookie: sys::binder_uintptr_t) -> Option<(u32, Arc<DeathRecipient>)> {
+        self.death_recipients.lock().unwrap().remove(&cookie)
+    }
+
+    /// Set the maximum amount of threads the driver may ask us to spawn, on
+    /// top of the main thread pool thread. See `sys::binder_set_max_threads`.
+    pub fn set_max_threads(&self, max_threads: u32) -> Result<()> {
+        unsafe { sys::binder_set_max_threads(self.fd, &max_threads)?; }
+        self.max_threads.store(max_threads as usize, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Start the thread pool, by spawning its main thread. Other threads will
+    /// be spawned when the driver asks for them, up to the value given to
+    /// `set_max_threads`.
+    ///
+    /// Calling this more than once does nothing.
+    pub fn start_thread_pool(process: &Arc<ProcessState>) {
+        if !process.thread_pool_started.swap(true, Ordering::SeqCst) {
+            ProcessState::spawn_pooled_thread(process, true);
+        }
+    }
+
+    /// Whether `start_thread_pool` was called, meaning incoming transactions
+    /// get handled without the other threads having to do anything.
+    pub fn thread_pool_started(&self) -> bool {
+        self.thread_pool_started.load(Ordering::SeqCst)
+    }
+
+    /// The layout of the interface tokens we write. Defaults to
+    /// `InterfaceTokenFormat::Legacy`, until `ServiceManager` finds out which
+    /// one the servicemanager understands.
+    pub fn interface_token_format(&self) -> InterfaceTokenFormat {
+        *self.interface_token_format.lock().unwrap()
+    }
+
+    /// Set the layout of the interface tokens we write, for every connection
+    /// of this process.
+    pub fn set_interface_token_format(&self, format: InterfaceTokenFormat) {
+        *self.interface_token_format.lock().unwrap() = format;
+    }
+
+    /// Called when the driver sends a `SpawnLooper`.
+    pub(crate) fn spawn_requested(process: &Arc<ProcessState>) {
+        // The driver already accounts for the maximum, but it doesn't know
+        // about threads that are still starting up.
+        // Reserve the slot before spawning, as several loopers may handle a
+        // `SpawnLooper` at the same time.
+        let max = process.max_threads.load(Ordering::SeqCst);
+        let mut running = process.running_threads.load(Ordering::SeqCst);
+        loop {
+            if running >= max {
+                debug!("Driver asked for a new looper, but we already have {} threads", max);
+                return
+            }
+            match process.running_threads.compare_exchange(running, running + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(current) => running = current
+            }
+        }
+        ProcessState::spawn_pooled_thread(process, false);
+    }
+
+    /// Start a thread pool thread. Unless it is the main one, its slot in
+    /// `running_threads` must already be reserved.
+
+    fn spawn_pooled_thread(process: &Arc<ProcessState>, is_main: bool) {
+        let id = process.thread_counter.fetch_add(1, Ordering::SeqCst);
+        let name = format!("Binder:{}_{:X}", unsafe { libc::getpid() }, id);
+        let thread_process = process.clone();
+        let res = thread::Builder::new().name(name).spawn(move || {
+            let mut conn = BinderConnection::new(thread_process.clone());
+            if let Err(err) = conn.join_thread_pool(is_main) {
+                error!("Binder thread pool thread exited with an error: {}", err);
+            }
+            conn.thread_exit();
+            if !is_main {
+                thread_process.running_threads.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+        if let Err(err) = res {
+            error!("Failed to spawn binder thread: {}", err);
+            if !is_main {
+                process.running_threads.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
@@ -12,74 +12,455 @@
 //! privileges : you can get access to other system-level services such as the
 //! telephony services, or you can even register your own system-level service.
 //!
+//! Android 11 replaced the C servicemanager with one implemented in AIDL. It
+//! uses the same transaction codes for the original methods, but its replies
+//! start with an exception header, and it supports a few more methods. We
+//! figure out which one we are talking to on first use.
+//!
 
 use std::rc::Rc;
 use std::cell::RefCell;
-use {Handle, OwnedParcel, IInterface, BinderResult, FIRST_CALL_TRANSACTION};
-use parcel::Parcel;
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread;
+use std::time::{Duration, Instant};
+use {Handle, IBinder, OwnedParcel, IInterface, BinderResult, BinderError, BinderErrorKind, FIRST_CALL_TRANSACTION};
+use local_binder::LocalBinder;
+use parcel::{Parcel, ParcelVectorExt, InterfaceTokenFormat, INTERFACE_HEADER_SYSTEM, INTERFACE_HEADER_VENDOR};
+use status::ExceptionCode;
+use byteorder::{ByteOrder, NativeEndian};
+
+// The stability levels of libbinder, see write_binder_with_stability.
+const STABILITY_VENDOR : u8 = 0b000011;
+const STABILITY_SYSTEM : u8 = 0b001100;
+
+/// The only version of the Android 12 `Stability::Category`.
+const STABILITY_CATEGORY_VERSION : u8 = 1;
+
+/// How the stability following binders sent to the AIDL servicemanager is
+/// laid out. The servicemanager fails requests with `BadType` if it can't read
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StabilityFormat {
+    /// Android 11, and 13 and later : the bare level, as an int32.
+    Level,
+    /// Android 12 : a `Stability::Category`, that is the version byte, two
+    /// reserved bytes and the level byte, read as a native int32.
+    Category
+}
+
+impl StabilityFormat {
+    fn encode(self, level: u8) -> i32 {
+        match self {
+            StabilityFormat::Level => level as i32,
+            StabilityFormat::Category => NativeEndian::read_i32(&[STABILITY_CATEGORY_VERSION, 0, 0, level])
+        }
+    }
+
+    /// The format of the Android versions that don't understand this one.
+    fn other(self) -> StabilityFormat {
+        match self {
+            StabilityFormat::Level => StabilityFormat::Category,
+            StabilityFormat::Category => StabilityFormat::Level
+        }
+    }
+}
+
+/// How long `get_service` waits for a service to show up, like libbinder.
+pub const GET_SERVICE_TIMEOUT_MS : u64 = 5000;
 
 pub struct ServiceManager {
-    handle: Rc<RefCell<Handle>>
+    handle: Rc<RefCell<Handle>>,
+    /// Whether the remote end is the AIDL servicemanager. None until we asked.
+    aidl: Option<bool>,
+    /// How the AIDL servicemanager reads the stability of binders. Switched
+    /// when it fails to read the one we sent, see `with_stability`.
+    stability: StabilityFormat
 }
 
+/// Services that should be dumped first when a bug report is taken.
+pub const DUMP_FLAG_PRIORITY_CRITICAL : i32 = 1 << 0;
+pub const DUMP_FLAG_PRIORITY_HIGH : i32 = 1 << 1;
+pub const DUMP_FLAG_PRIORITY_NORMAL : i32 = 1 << 2;
+/// The priority of services that didn't specify one.
+pub const DUMP_FLAG_PRIORITY_DEFAULT : i32 = 1 << 3;
+pub const DUMP_FLAG_PRIORITY_ALL : i32 = DUMP_FLAG_PRIORITY_CRITICAL | DUMP_FLAG_PRIORITY_HIGH
+    | DUMP_FLAG_PRIORITY_NORMAL | DUMP_FLAG_PRIORITY_DEFAULT;
+/// Services supporting dumping in the protobuf format.
+pub const DUMP_FLAG_PROTO : i32 = 1 << 4;
+
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-enum ServiceManagerProtocol {
-    #[allow(dead_code)] // TODO: Remove allow(dead_code)
+pub(crate) enum ServiceManagerProtocol {
     GetService = FIRST_CALL_TRANSACTION,
     CheckService,
     AddService,
-    ListServices
+    ListServices,
+    // Only implemented by the AIDL servicemanager.
+    RegisterForNotifications,
+    UnregisterForNotifications,
+    IsDeclared
 }
 
 impl ServiceManager {
-    /*/// Retrieve an existing service, blocking for a few seconds if it doesn't
-    /// yet exist
-    fn getService<T>(name: &str) -> Handle {
-        // TODO: basically call check service in loop
-    }*/
-
-    // TODO: I don't need to pass name, I can just use T::get_interface_descriptor() !
-    /// Retrieve an existing service
-    pub fn check_service<T: IInterface>(&mut self, name: &str) -> BinderResult<Option<T>> {
+    /// Whether we are talking to the AIDL servicemanager. The first call finds
+    /// out which servicemanager it is, and the interface token format it
+    /// understands, which becomes the one of the whole process.
+    ///
+    /// Only a servicemanager that doesn't know a transaction tells us it is an
+    /// older one. Other errors are returned, and we ask again next time.
+    fn is_aidl(&mut self) -> BinderResult<bool> {
+        if let Some(aidl) = self.aidl {
+            return Ok(aidl)
+        }
+
+        let (aidl, format) = self.detect_protocol()?;
+        debug!("Talking to the {} servicemanager, using {:?} interface tokens", if aidl { "AIDL" } else { "legacy" }, format);
+        self.handle.borrow().conn.process().set_interface_token_format(format);
+        self.aidl = Some(aidl);
+        Ok(aidl)
+    }
+
+    /// Find out whether the servicemanager is the AIDL one, and the interface
+    /// token format it understands.
+    fn detect_protocol(&mut self) -> BinderResult<(bool, InterfaceTokenFormat)> {
+        // The AIDL servicemanager (Android 11 and later) knows IsDeclared.
+        // Like every libbinder process, it rejects interface tokens with
+        // another header than its own, which is different for vendor ones.
+        for &header in &[INTERFACE_HEADER_SYSTEM, INTERFACE_HEADER_VENDOR] {
+            let format = InterfaceTokenFormat::Header(header);
+            match self.probe(ServiceManagerProtocol::IsDeclared, format) {
+                Ok(()) => return Ok((true, format)),
+                Err(BinderError(BinderErrorKind::BadType, _)) => continue,
+                Err(ref err) if is_unknown_transaction(err) => break,
+                Err(err) => return Err(err)
+            }
+        }
+
+        // The C servicemanager knows CheckService. Since Android 10, it skips
+        // the work source of the tokens it reads. It fails every request with
+        // a token it reads differently, see is_unknown_transaction.
+        for &format in &[InterfaceTokenFormat::WorkSource, InterfaceTokenFormat::Legacy] {
+            match self.probe(ServiceManagerProtocol::CheckService, format) {
+                Ok(()) => return Ok((false, format)),
+                Err(ref err) if is_unknown_transaction(err) => continue,
+                Err(err) => return Err(err)
+            }
+        }
+        Err(BinderErrorKind::BadType.into())
+    }
+
+    /// Send `code` with an empty name, writing the interface token in
+    /// `format`.
+    fn probe(&mut self, code: ServiceManagerProtocol, format: InterfaceTokenFormat) -> BinderResult<()> {
         let mut data = OwnedParcel::new(self.handle.borrow().conn.clone());
-        data.write_interface_token(ServiceManager::get_interface_descriptor());
-        data.write_string16(name);
-        let mut reply = self.handle.borrow_mut().transact(ServiceManagerProtocol::CheckService as u32, &mut data, 0)?;
-        Ok(reply.read_strong_binder()?.map(|e| T::from_handle(e)))
+        data.write_interface_token_as(ServiceManager::get_interface_descriptor(), format);
+        data.write_string16("");
+        self.handle.borrow().transact(code as u32, &mut data, 0)?;
+        Ok(())
+    }
+
+    /// Retrieve an existing service, waiting up to `GET_SERVICE_TIMEOUT_MS`
+    /// for it to be registered. Returns None if it still doesn't exist by
+    /// then.
+    pub fn get_service<T: IInterface>(&mut self, name: &str) -> BinderResult<Option<T>> {
+        self.get_service_timeout(name, Duration::from_millis(GET_SERVICE_TIMEOUT_MS))
+    }
+
+    /// Retrieve an existing service, waiting up to `timeout` for it to be
+    /// registered. Returns None if it still doesn't exist by then.
+    pub fn get_service_timeout<T: IInterface>(&mut self, name: &str, timeout: Duration) -> BinderResult<Option<T>> {
+        let start = Instant::now();
+        let mut delay = Duration::from_millis(10);
+        loop {
+            if let Some(service) = self.check_service(name)? {
+                return Ok(Some(service))
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                warn!("Service {} didn't start, giving up", name);
+                return Ok(None)
+            }
+            debug!("Waiting for service {}...", name);
+            thread::sleep(::std::cmp::min(delay, timeout - elapsed));
+            delay = ::std::cmp::min(delay * 2, Duration::from_secs(1));
+        }
+    }
+
+    /// Retrieve a service, blocking until it gets registered.
+    ///
+    /// With the AIDL servicemanager, we ask to be notified of the
+    /// registration. The notification is delivered to the thread pool, so it
+    /// has to be started (see `ProcessState::start_thread_pool`). Otherwise,
+    /// we poll, like with the legacy servicemanager.
+    pub fn wait_for_service<T: IInterface>(&mut self, name: &str) -> BinderResult<T> {
+        if let Some(service) = self.check_service(name)? {
+            return Ok(service)
+        }
+
+        let process = self.handle.borrow().conn.process();
+        if !self.is_aidl()? || !process.thread_pool_started() {
+            loop {
+                if let Some(service) = self.get_service_timeout(name, Duration::from_secs(60))? {
+                    return Ok(service)
+                }
+            }
+        }
+
+        let callback = Arc::new(WaitCallback {
+            registered: Mutex::new(false),
+            cond: Condvar::new()
+        });
+        // Unregistered when we return.
+        let _registration = self.register_for_notifications(name, callback.clone())?;
+        loop {
+            // The notification is only a hint : the binder it carries belongs
+            // to another thread, so we ask for our own.
+            if let Some(service) = self.check_service(name)? {
+                return Ok(service)
+            }
+            let registered = callback.registered.lock().unwrap();
+            let mut registered = if !*registered {
+                // Wake up once in a while, in case the notification got lost.
+                callback.cond.wait_timeout(registered, Duration::from_secs(1)).unwrap().0
+            } else {
+                registered
+            };
+            *registered = false;
+        }
+    }
+
+    /// Get notified when the service `name` gets registered, until the
+    /// returned `ServiceRegistration` is dropped or given to
+    /// `unregister_for_notifications`. If it already is, the callback is
+    /// called right away.
+    ///
+    /// The callback is called by the thread that receives the notification, so
+    /// the thread pool has to be started (see
+    /// `ProcessState::start_thread_pool`), or some thread has to handle
+    /// incoming transactions.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidOperation` if the servicemanager doesn't support
+    ///   notifications, which is the case before Android 11
+    /// - `PermissionDenied` if we aren't allowed to look the service up
+    pub fn register_for_notifications(&mut self, name: &str, callback: Arc<ServiceCallback>) -> BinderResult<ServiceRegistration> {
+        if !self.is_aidl()? {
+            return Err(BinderErrorKind::InvalidOperation.into())
+        }
+        let binder : Arc<LocalBinder> = Arc::new(ServiceCallbackBinder { callback: callback });
+        let handle = &self.handle;
+        with_stability(&mut self.stability, |stability| {
+            notification_request(handle, ServiceManagerProtocol::RegisterForNotifications, name, binder.clone(), stability)
+        })?;
+        Ok(ServiceRegistration {
+            name: name.to_owned(),
+            handle: self.handle.clone(),
+            binder: Some(binder),
+            stability: self.stability
+        })
+    }
+
+    /// Stop the notifications requested by `register_for_notifications`.
+    /// Unlike dropping the registration, this tells whether it worked.
+    pub fn unregister_for_notifications(&mut self, mut registration: ServiceRegistration) -> BinderResult<()> {
+        registration.unregister()
+    }
+
+    /// Retrieve an existing service registered under the descriptor of `T`,
+    /// without waiting for it. See `check_service`.
+    pub fn check_service_by_descriptor<T: IInterface>(&mut self) -> BinderResult<Option<T>> {
+        self.check_service(T::get_interface_descriptor())
+    }
+
+    /// Retrieve a service registered under the descriptor of `T`, waiting for
+    /// it like `get_service` does.
+    pub fn get_service_by_descriptor<T: IInterface>(&mut self) -> BinderResult<Option<T>> {
+        self.get_service(T::get_interface_descriptor())
     }
 
-    // TODO: Finish this. Instead of a handle, this should take the eventual
-    // Binder enum (that can contain either a Local binder or a Handle).
-    pub fn add_service(&mut self, name: &str, handle: Rc<RefCell<Handle>>, allow_isolated: bool) -> BinderResult<()> {
+    /// Retrieve an existing service, without waiting for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InterfaceMismatch` if the service doesn't implement `T`.
+    pub fn check_service<T: IInterface>(&mut self, name: &str) -> BinderResult<Option<T>> {
+        let aidl = self.is_aidl()?;
         let mut data = OwnedParcel::new(self.handle.borrow().conn.clone());
         data.write_interface_token(ServiceManager::get_interface_descriptor());
         data.write_string16(name);
-        data.write_strong_binder(Some(handle));
-        data.write_i32(if allow_isolated { 1 } else { 0 });
-        self.handle.borrow_mut().transact(ServiceManagerProtocol::AddService as u32, &mut data, 0).unwrap();
-        panic!("Not implemented yet");
+        let mut reply = self.handle.borrow().transact(ServiceManagerProtocol::CheckService as u32, &mut data, 0)?;
+        if aidl {
+            read_status(&mut reply)?;
+        }
+        match reply.read_strong_binder()? {
+            Some(IBinder::Remote(handle)) => {
+                let descriptor = handle.borrow().get_interface_descriptor()?;
+                if descriptor != T::get_interface_descriptor() {
+                    error!("Service {} implements {} instead of {}", name, descriptor, T::get_interface_descriptor());
+                    return Err(BinderErrorKind::InterfaceMismatch(T::get_interface_descriptor().to_owned(), descriptor).into())
+                }
+                Ok(Some(T::from_handle(handle)))
+            },
+            // TODO: IInterface can only wrap remote binders for now.
+            Some(IBinder::Local(_)) => Err(BinderErrorKind::BadType.into()),
+            None => Ok(None)
+        }
     }
 
-    /// List all registered system services.
-    pub fn list_services(&mut self) -> Vec<String> {
+    /// Register `binder` as the service `name`.
+    ///
+    /// `allow_isolated` lets isolated processes look the service up.
+    /// `dump_priority` is a combination of the `DUMP_FLAG_*` flags, telling
+    /// when the service gets dumped in bug reports. Servicemanagers older than
+    /// Android 9 ignore it.
+    ///
+    /// Local binders can only answer other processes if some thread handles
+    /// incoming transactions, usually by starting the thread pool (see
+    /// `ProcessState::start_thread_pool`).
+    ///
+    /// # Errors
+    ///
+    /// - `PermissionDenied` if we aren't allowed to register this service
+    /// - `BadValue` if the servicemanager rejected the name
+    ///
+    /// A service registered under the same name is replaced.
+    pub fn add_service(&mut self, name: &str, binder: IBinder, allow_isolated: bool, dump_priority: i32) -> BinderResult<()> {
+        let aidl = self.is_aidl()?;
+        let handle = &self.handle;
+        let add = |stability: Option<StabilityFormat>| {
+            let mut data = OwnedParcel::new(handle.borrow().conn.clone());
+            data.write_interface_token(ServiceManager::get_interface_descriptor());
+            data.write_string16(name);
+            match stability {
+                Some(stability) => write_binder_with_stability(&mut data, binder.clone(), stability),
+                None => data.write_strong_binder(Some(binder.clone()))
+            }
+            data.write_i32(if allow_isolated { 1 } else { 0 });
+            data.write_i32(dump_priority);
+            // The legacy servicemanager answers failures with a status code,
+            // which turns into the matching error here.
+            let mut reply = handle.borrow().transact(ServiceManagerProtocol::AddService as u32, &mut data, 0)?;
+            if stability.is_some() {
+                read_status(&mut reply)?;
+            }
+            Ok(())
+        };
+        if aidl {
+            with_stability(&mut self.stability, |stability| add(Some(stability)))
+        } else {
+            add(None)
+        }
+    }
+
+    /// List the registered services whose dump priority matches
+    /// `dump_priority`. Use `DUMP_FLAG_PRIORITY_ALL` to get all of them.
+    /// Servicemanagers older than Android 9 ignore the filter.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PermissionDenied` if we aren't allowed to list services.
+    ///
+    /// The legacy servicemanager answers with the same error when we ask for
+    /// an index past the end of the list. A failure on the first index is
+    /// considered a denial, so an empty list of services can't be told apart
+    /// from one.
+    pub fn list_services(&mut self, dump_priority: i32) -> BinderResult<Vec<String>> {
+        let aidl = self.is_aidl()?;
         let mut data = OwnedParcel::new(self.handle.borrow().conn.clone());
+        if aidl {
+            data.write_interface_token(ServiceManager::get_interface_descriptor());
+            data.write_i32(dump_priority);
+            let mut reply = self.handle.borrow().transact(ServiceManagerProtocol::ListServices as u32, &mut data, 0)?;
+            read_status(&mut reply)?;
+            // The servicemanager never sends null names, skip them if it does.
+            let names = reply.read_string16_vector()?;
+            return Ok(names.into_iter().filter_map(|name| name).collect())
+        }
+
         let mut res = Vec::new();
         for i in 0.. {
             data.clear();
-            data.write_interface_token(Self::get_interface_descriptor());
+            data.write_interface_token(ServiceManager::get_interface_descriptor());
             data.write_i32(i);
-            println!("Reading service {}", i);
-            match self.handle.borrow_mut().transact(ServiceManagerProtocol::ListServices as u32, &mut data, 0) {
-                Ok(mut reply) => {
-                    let service = reply.read_string16().expect("read_string16 should work !");
-                    println!("Got a service {}", service);
-                    res.push(service);
-                },
-                Err(_) => break
+            data.write_i32(dump_priority);
+            match self.handle.borrow().transact(ServiceManagerProtocol::ListServices as u32, &mut data, 0) {
+                Ok(mut reply) => res.push(reply.read_string16()?),
+                // The end of the list, for the C servicemanager and ours.
+                Err(BinderError(BinderErrorKind::BadIndex, _)) => break,
+                Err(BinderError(BinderErrorKind::PermissionDenied, _)) if i > 0 => break,
+                Err(err) => return Err(err)
             }
         }
-        res
+        Ok(res)
+    }
+}
+
+/// Send a `registerForNotifications` or `unregisterForNotifications`. Only the
+/// AIDL servicemanager knows them.
+fn notification_request(handle: &Rc<RefCell<Handle>>, code: ServiceManagerProtocol, name: &str, callback: Arc<LocalBinder>, stability: StabilityFormat) -> BinderResult<()> {
+    let mut data = OwnedParcel::new(handle.borrow().conn.clone());
+    data.write_interface_token(ServiceManager::get_interface_descriptor());
+    data.write_string16(name);
+    write_binder_with_stability(&mut data, IBinder::Local(callback), stability);
+    let mut reply = handle.borrow().transact(code as u32, &mut data, 0)?;
+    read_status(&mut reply)
+}
+
+/// Write a binder for the AIDL servicemanager. Since Android 11, libbinder
+/// follows every binder with its stability, telling whose ABI it is bound to.
+/// We claim the one of the partition named by our interface token header.
+fn write_binder_with_stability(data: &mut OwnedParcel, binder: IBinder, format: StabilityFormat) {
+    data.write_strong_binder(Some(binder));
+    let level = match data.conn_mut().process().interface_token_format() {
+        InterfaceTokenFormat::Header(INTERFACE_HEADER_VENDOR) => STABILITY_VENDOR,
+        _ => STABILITY_SYSTEM
+    };
+    data.write_i32(format.encode(level));
+}
+
+/// Run `request`, which sends binders to the AIDL servicemanager with their
+/// stability in the given format. We can't tell Android 12 apart from the
+/// other versions, so if the servicemanager can't read the stability, the
+/// request is retried in the other format, which is kept if it works.
+fn with_stability<T, F>(format: &mut StabilityFormat, mut request: F) -> BinderResult<T>
+    where F: FnMut(StabilityFormat) -> BinderResult<T>
+{
+    match request(*format) {
+        Err(BinderError(BinderErrorKind::BadType, _)) => {
+            let other = format.other();
+            let res = request(other)?;
+            debug!("The servicemanager reads binder stability as a {:?}", other);
+            *format = other;
+            Ok(res)
+        },
+        res => res
+    }
+}
+
+/// Whether the servicemanager doesn't understand a request. libbinder answers
+/// those with `UnknownTransaction`, but the C servicemanager fails everything
+/// it can't handle with a bare -1, which reads as `PermissionDenied`.
+fn is_unknown_transaction(err: &BinderError) -> bool {
+    match *err.kind() {
+        BinderErrorKind::UnknownTransaction | BinderErrorKind::PermissionDenied => true,
+        _ => false
+    }
+}
+
+/// Read the exception header of an AIDL servicemanager reply, mapping the
+/// exceptions it throws to the errors the legacy one sends.
+fn read_status<P: Parcel + ?Sized>(reply: &mut P) -> BinderResult<()> {
+    match reply.read_exception()?.into_result() {
+        Err(BinderError(BinderErrorKind::ServiceException(status), state)) => {
+            let kind = match status.exception_code() {
+                ExceptionCode::Security => BinderErrorKind::PermissionDenied,
+                ExceptionCode::IllegalArgument | ExceptionCode::NullPointer => BinderErrorKind::BadValue,
+                _ => BinderErrorKind::ServiceException(status)
+            };
+            Err(BinderError(kind, state))
+        },
+        res => res
     }
 }
 
@@ -88,6 +469,96 @@ impl IInterface for ServiceManager {
         "android.os.IServiceManager"
     }
     fn from_handle(handle: Rc<RefCell<Handle>>) -> ServiceManager {
-        ServiceManager { handle: handle }
+        ServiceManager {
+            handle: handle,
+            aidl: None,
+            stability: StabilityFormat::Level
+        }
+    }
+}
+
+/// Gets notified when a service is registered. See
+/// `ServiceManager::register_for_notifications`.
+///
+/// Notifications are delivered by whichever thread receives them, which is why
+/// callbacks have to be `Send` and `Sync`.
+pub trait ServiceCallback: Send + Sync {
+    /// Called when the service `name` was registered. `binder` belongs to the
+    /// calling thread's connection.
+    fn on_registration(&self, name: &str, binder: IBinder);
+}
+
+/// A notification request. The notifications stop when it is dropped, or
+/// given to `ServiceManager::unregister_for_notifications`.
+pub struct ServiceRegistration {
+    name: String,
+    /// The servicemanager we registered with.
+    handle: Rc<RefCell<Handle>>,
+    /// The binder we sent to the servicemanager, which it uses to identify the
+    /// request. None once unregistered.
+    binder: Option<Arc<LocalBinder>>,
+    /// The stability format the servicemanager read when we registered.
+    stability: StabilityFormat
+}
+
+impl ServiceRegistration {
+    /// The name of the service we are waiting for.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn unregister(&mut self) -> BinderResult<()> {
+        match self.binder.take() {
+            Some(binder) => notification_request(&self.handle, ServiceManagerProtocol::UnregisterForNotifications, &self.name, binder, self.stability),
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for ServiceRegistration {
+    fn drop(&mut self) {
+        if let Err(err) = self.unregister() {
+            warn!("Failed to unregister notification for {}: {}", self.name, err);
+        }
+    }
+}
+
+/// The `IServiceCallback` given to the AIDL servicemanager.
+struct ServiceCallbackBinder {
+    callback: Arc<ServiceCallback>
+}
+
+impl LocalBinder for ServiceCallbackBinder {
+    fn get_interface_descriptor(&self) -> &str {
+        "android.os.IServiceCallback"
+    }
+
+    fn on_transact(&self, code: u32, data: &mut Parcel, _reply: &mut OwnedParcel, _flags: u32) -> BinderResult<()> {
+        // oneway void onRegistration(String name, IBinder binder)
+        if code != FIRST_CALL_TRANSACTION {
+            return Err(BinderErrorKind::UnknownTransaction.into())
+        }
+        data.enforce_interface(self.get_interface_descriptor())?;
+        let name = data.read_string16()?;
+        match data.read_strong_binder()? {
+            Some(binder) => {
+                self.callback.on_registration(&name, binder);
+                Ok(())
+            },
+            None => Err(BinderErrorKind::UnexpectedNull.into())
+        }
+    }
+}
+
+/// The callback used by `wait_for_service`.
+struct WaitCallback {
+    registered: Mutex<bool>,
+    cond: Condvar
+}
+
+impl ServiceCallback for WaitCallback {
+    fn on_registration(&self, _name: &str, _binder: IBinder) {
+        *self.registered.lock().unwrap() = true;
+        self.cond.notify_all();
     }
 }
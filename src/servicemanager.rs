@@ -0,0 +1,260 @@
+//!
+//! Service Manager implementation
+//!
+//! The server side of `service_manager::ServiceManager`, for systems that
+//! don't have an Android servicemanager to talk to. It registers itself as the
+//! context manager, and keeps track of the services registered through it.
+//!
+//! It implements the protocol of the legacy C servicemanager. Like it, it
+//! doesn't do any permission checking beyond `allow_isolated`.
+//!
+//! ```no_run
+//! # use binder::BinderConnection;
+//! let mut conn = BinderConnection::open().unwrap();
+//! binder::servicemanager::run(&mut conn).unwrap();
+//! ```
+//!
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, ThreadId};
+
+use {BinderConnection, DeathRecipient, IBinder, IInterface, Result, BinderResult, BinderErrorKind};
+use local_binder::LocalBinder;
+use parcel::{Parcel, OwnedParcel};
+use service_manager::{self, ServiceManager, ServiceManagerProtocol};
+
+/// The longest service name accepted, like the C servicemanager.
+const MAX_NAME_LEN : usize = 127;
+
+// Isolated processes have their own uid range in every user.
+const AID_USER : u32 = 100000;
+const AID_ISOLATED_START : u32 = 99000;
+const AID_ISOLATED_END : u32 = 99999;
+
+struct Service {
+    binder: IBinder,
+    allow_isolated: bool,
+    dump_priority: i32
+}
+
+thread_local! {
+    /// The services of every service manager owned by this thread, indexed by
+    /// the id of their registry. Remote binders can only be used from the
+    /// thread whose connection created them, so they never leave it.
+    static SERVICES : RefCell<HashMap<usize, BTreeMap<String, Service>>> = RefCell::new(HashMap::new());
+}
+
+static NEXT_REGISTRY_ID : AtomicUsize = AtomicUsize::new(0);
+
+/// A handle to the services of a service manager, which can only be used from
+/// the thread that created it.
+#[derive(Clone, Copy)]
+struct Registry {
+    owner: ThreadId,
+    id: usize
+}
+
+impl Registry {
+    fn new() -> Registry {
+        Registry {
+            owner: thread::current().id(),
+            id: NEXT_REGISTRY_ID.fetch_add(1, Ordering::SeqCst)
+        }
+    }
+
+    fn with_services<T, F>(&self, f: F) -> BinderResult<T>
+        where F: FnOnce(&mut BTreeMap<String, Service>) -> BinderResult<T>
+    {
+        if thread::current().id() != self.owner {
+            error!("The service manager was called from a thread it doesn't belong to");
+            return Err(BinderErrorKind::InvalidOperation.into())
+        }
+        SERVICES.with(|services| {
+            let mut services = services.try_borrow_mut().map_err(|_| BinderErrorKind::WouldBlock)?;
+            f(services.entry(self.id).or_insert_with(BTreeMap::new))
+        })
+    }
+}
+
+/// Removes the services of a process when it dies.
+struct ServiceDeath {
+    registry: Registry
+}
+
+impl DeathRecipient for ServiceDeath {
+    fn binder_died(&self, handle: u32) {
+        let _ = self.registry.with_services(|services| {
+            let dead : Vec<String> = services.iter()
+                .filter(|&(_, service)| is_handle(&service.binder, handle))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in dead {
+                info!("Service {} died", name);
+                services.remove(&name);
+            }
+            Ok(())
+        });
+    }
+}
+
+fn is_handle(binder: &IBinder, handle: u32) -> bool {
+    match *binder {
+        IBinder::Remote(ref remote) => remote.borrow().handle == handle,
+        IBinder::Local(_) => false
+    }
+}
+
+fn is_isolated(uid: u32) -> bool {
+    let appid = uid % AID_USER;
+    appid >= AID_ISOLATED_START && appid <= AID_ISOLATED_END
+}
+
+/// The service manager, as a local binder. It has to be registered as the
+/// context manager, and driven by the thread that created it. See `run`.
+pub struct ServiceManagerService {
+    registry: Registry
+}
+
+impl ServiceManagerService {
+    /// Create an empty service manager, owned by the current thread.
+    pub fn new() -> ServiceManagerService {
+        ServiceManagerService {
+            registry: Registry::new()
+        }
+    }
+
+    fn get_service(&self, data: &mut Parcel, reply: &mut OwnedParcel) -> BinderResult<()> {
+        let name = data.read_string16()?;
+        let uid = data.conn_mut().calling_uid();
+        let binder = self.registry.with_services(|services| {
+            Ok(match services.get(&name) {
+                Some(service) if service.allow_isolated || !is_isolated(uid) => Some(service.binder.clone()),
+                Some(_) => {
+                    warn!("Isolated uid {} asked for service {}", uid, name);
+                    None
+                },
+                None => None
+            })
+        })?;
+        reply.write_strong_binder(binder);
+        Ok(())
+    }
+
+    fn add_service(&self, data: &mut Parcel, reply: &mut OwnedParcel) -> BinderResult<()> {
+        let name = data.read_string16()?;
+        let binder = match data.read_strong_binder()? {
+            Some(binder) => binder,
+            None => return Err(BinderErrorKind::BadValue.into())
+        };
+        let allow_isolated = data.read_i32()? != 0;
+        // Older clients don't send a dump priority.
+        let dump_priority = if data.has_data() {
+            data.read_i32()?
+        } else {
+            service_manager::DUMP_FLAG_PRIORITY_DEFAULT
+        };
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(BinderErrorKind::BadValue.into())
+        }
+
+        let registry = self.registry;
+        registry.with_services(|services| {
+            if let IBinder::Remote(ref remote) = binder {
+                let handle = remote.borrow().handle;
+                // One notification per process is enough to clean all of its
+                // services up.
+                if !services.values().any(|service| is_handle(&service.binder, handle)) {
+                    let recipient = Arc::new(ServiceDeath { registry: registry });
+                    remote.borrow_mut().link_to_death(recipient)?;
+                }
+            }
+            debug!("Adding service {} (allow_isolated: {}, dump_priority: {:#x})", name, allow_isolated, dump_priority);
+            services.insert(name, Service {
+                binder: binder,
+                allow_isolated: allow_isolated,
+                dump_priority: dump_priority
+            });
+            Ok(())
+        })?;
+        reply.write_i32(0);
+        Ok(())
+    }
+
+    fn list_services(&self, data: &mut Parcel, reply: &mut OwnedParcel) -> BinderResult<()> {
+        let index = data.read_i32()?;
+        let dump_priority = if data.has_data() {
+            data.read_i32()?
+        } else {
+            service_manager::DUMP_FLAG_PRIORITY_ALL
+        };
+        if index < 0 {
+            return Err(BinderErrorKind::BadIndex.into())
+        }
+        let name = self.registry.with_services(|services| {
+            Ok(services.iter()
+                .filter(|&(_, service)| service.dump_priority & dump_priority != 0)
+                .map(|(name, _)| name.clone())
+                .nth(index as usize))
+        })?;
+        match name {
+            Some(name) => {
+                reply.write_string16(&name);
+                Ok(())
+            },
+            // This is how clients know they reached the end of the list.
+            None => Err(BinderErrorKind::BadIndex.into())
+        }
+    }
+}
+
+impl Drop for ServiceManagerService {
+    fn drop(&mut self) {
+        // Binders can only be released from the owner thread. If we are
+        // dropped elsewhere, they are released when it exits.
+        if thread::current().id() == self.registry.owner {
+            let id = self.registry.id;
+            let _ = SERVICES.try_with(|services| {
+                if let Ok(mut services) = services.try_borrow_mut() {
+                    services.remove(&id);
+                }
+            });
+        }
+    }
+}
+
+impl LocalBinder for ServiceManagerService {
+    fn get_interface_descriptor(&self) -> &str {
+        ServiceManager::get_interface_descriptor()
+    }
+
+    fn on_transact(&self, code: u32, data: &mut Parcel, reply: &mut OwnedParcel, _flags: u32) -> BinderResult<()> {
+        data.enforce_interface(ServiceManager::get_interface_descriptor())?;
+        if code == ServiceManagerProtocol::GetService as u32 || code == ServiceManagerProtocol::CheckService as u32 {
+            self.get_service(data, reply)
+        } else if code == ServiceManagerProtocol::AddService as u32 {
+            self.add_service(data, reply)
+        } else if code == ServiceManagerProtocol::ListServices as u32 {
+            self.list_services(data, reply)
+        } else {
+            Err(BinderErrorKind::UnknownTransaction.into())
+        }
+    }
+}
+
+/// Become the context manager of the device `conn` talks to, and serve
+/// requests on the current thread.
+///
+/// Failed requests, e.g. because their caller died before we replied, are
+/// logged and don't stop the service manager. This only returns if talking to
+/// the driver fails.
+pub fn run(conn: &mut BinderConnection) -> Result<()> {
+    // The registry belongs to this thread, the driver shouldn't dispatch
+    // requests to any other.
+    conn.process().set_max_threads(0)?;
+    conn.process().become_context_manager(Arc::new(ServiceManagerService::new()))?;
+    conn.join_thread_pool(true)?;
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//!
+//! The exception header at the start of AIDL replies.
+//!
+//! Services implemented in Java (and C++ services using `binder::Status`)
+//! start their replies with an exception code. When it isn't `EX_NONE`, it is
+//! followed by a message, a stack-trace header that we skip, and possibly a
+//! service-specific error code.
+//!
+
+use std::fmt;
+use parcel::{Parcel, OwnedParcel};
+use error::{BinderResult, BinderErrorKind};
+
+const EX_NONE : i32 = 0;
+const EX_SECURITY : i32 = -1;
+const EX_BAD_PARCELABLE : i32 = -2;
+const EX_ILLEGAL_ARGUMENT : i32 = -3;
+const EX_NULL_POINTER : i32 = -4;
+const EX_ILLEGAL_STATE : i32 = -5;
+const EX_NETWORK_MAIN_THREAD : i32 = -6;
+const EX_UNSUPPORTED_OPERATION : i32 = -7;
+const EX_SERVICE_SPECIFIC : i32 = -8;
+const EX_PARCELABLE : i32 = -9;
+// Special codes, used to prefix the reply with headers that aren't exceptions.
+const EX_HAS_NOTED_APPOPS_REPLY_HEADER : i32 = -127;
+const EX_HAS_REPLY_HEADER : i32 = -128;
+
+/// The exception thrown by a service, as sent over the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionCode {
+    None,
+    Security,
+    BadParcelable,
+    IllegalArgument,
+    NullPointer,
+    IllegalState,
+    NetworkMainThread,
+    UnsupportedOperation,
+    ServiceSpecific,
+    Parcelable,
+    Unknown(i32)
+}
+
+impl ExceptionCode {
+    pub fn from_i32(code: i32) -> ExceptionCode {
+        use self::ExceptionCode::*;
+
+        match code {
+            EX_NONE                   => None,
+            EX_SECURITY               => Security,
+            EX_BAD_PARCELABLE         => BadParcelable,
+            EX_ILLEGAL_ARGUMENT       => IllegalArgument,
+            EX_NULL_POINTER           => NullPointer,
+            EX_ILLEGAL_STATE          => IllegalState,
+            EX_NETWORK_MAIN_THREAD    => NetworkMainThread,
+            EX_UNSUPPORTED_OPERATION  => UnsupportedOperation,
+            EX_SERVICE_SPECIFIC       => ServiceSpecific,
+            EX_PARCELABLE             => Parcelable,
+            code                      => Unknown(code)
+        }
+    }
+
+    pub fn to_i32(&self) -> i32 {
+        use self::ExceptionCode::*;
+
+        match *self {
+            None                 => EX_NONE,
+            Security             => EX_SECURITY,
+            BadParcelable        => EX_BAD_PARCELABLE,
+            IllegalArgument      => EX_ILLEGAL_ARGUMENT,
+            NullPointer          => EX_NULL_POINTER,
+            IllegalState         => EX_ILLEGAL_STATE,
+            NetworkMainThread    => EX_NETWORK_MAIN_THREAD,
+            UnsupportedOperation => EX_UNSUPPORTED_OPERATION,
+            ServiceSpecific      => EX_SERVICE_SPECIFIC,
+            Parcelable           => EX_PARCELABLE,
+            Unknown(code)        => code
+        }
+    }
+}
+
+/// The result of an AIDL call : either no exception, or an exception with its
+/// message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    exception: ExceptionCode,
+    message: String,
+    service_specific_error: i32
+}
+
+impl Status {
+    pub fn ok() -> Status {
+        Status {
+            exception: ExceptionCode::None,
+            message: String::new(),
+            service_specific_error: 0
+        }
+    }
+
+    pub fn from_exception(exception: ExceptionCode, message: &str) -> Status {
+        Status {
+            exception: exception,
+            message: message.to_owned(),
+            service_specific_error: 0
+        }
+    }
+
+    pub fn from_service_specific_error(error: i32, message: &str) -> Status {
+        Status {
+            exception: ExceptionCode::ServiceSpecific,
+            message: message.to_owned(),
+            service_specific_error: error
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.exception == ExceptionCode::None
+    }
+
+    pub fn exception_code(&self) -> ExceptionCode {
+        self.exception
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The error code given by the service, for `ServiceSpecific` exceptions.
+    pub fn service_specific_error(&self) -> i32 {
+        self.service_specific_error
+    }
+
+    /// Turn an exception into a `ServiceException` error.
+    pub fn into_result(self) -> BinderResult<()> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(BinderErrorKind::ServiceException(self).into())
+        }
+    }
+
+    /// Read the exception header. Called by `Parcel::read_exception`.
+    pub fn read_from_parcel<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<Status> {
+        let mut code = parcel.read_i32()?;
+        if code == EX_HAS_REPLY_HEADER {
+            // Fat replies have no exceptions.
+            skip_header(parcel)?;
+            return Ok(Status::ok())
+        }
+        if code == EX_HAS_NOTED_APPOPS_REPLY_HEADER {
+            skip_header(parcel)?;
+            code = parcel.read_i32()?;
+        }
+        if code == EX_NONE {
+            return Ok(Status::ok())
+        }
+
+        let message = parcel.read_nullable_string16()?.unwrap_or_default();
+        // Skip over the remote stack trace.
+        let stack_trace_size = parcel.read_i32()?;
+        if stack_trace_size < 0 || stack_trace_size as usize > parcel.data_avail() {
+            return Err(BinderErrorKind::BadValue.into())
+        }
+        let pos = parcel.data_position();
+        parcel.set_data_position(pos + stack_trace_size as usize)?;
+
+        let mut service_specific_error = 0;
+        if code == EX_SERVICE_SPECIFIC {
+            service_specific_error = parcel.read_i32()?;
+        } else if code == EX_PARCELABLE {
+            // The parcelable describing the exception, which we don't care
+            // about.
+            skip_header(parcel)?;
+        }
+
+        Ok(Status {
+            exception: ExceptionCode::from_i32(code),
+            message: message,
+            service_specific_error: service_specific_error
+        })
+    }
+
+    /// Write the exception header. Called by `OwnedParcel::write_exception`.
+    pub fn write_to_parcel(&self, parcel: &mut OwnedParcel) {
+        parcel.write_i32(self.exception.to_i32());
+        if self.is_ok() {
+            return
+        }
+        parcel.write_string16(&self.message);
+        // Empty stack trace.
+        parcel.write_i32(0);
+        if self.exception == ExceptionCode::ServiceSpecific {
+            parcel.write_i32(self.service_specific_error);
+        } else if self.exception == ExceptionCode::Parcelable {
+            // Empty parcelable. Its size includes the size itself.
+            parcel.write_i32(4);
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.exception {
+            ExceptionCode::None => write!(f, "No error"),
+            ExceptionCode::ServiceSpecific => write!(f, "Service-specific error {}: {}", self.service_specific_error, self.message),
+            exception => write!(f, "{:?} exception: {}", exception, self.message)
+        }
+    }
+}
+
+/// Skip a header made of its size, including the size itself, followed by its
+/// content.
+fn skip_header<P: Parcel + ?Sized>(parcel: &mut P) -> BinderResult<()> {
+    let start = parcel.data_position();
+    let avail = parcel.data_avail();
+    let size = parcel.read_i32()?;
+    if size < 0 || size as usize > avail {
+        return Err(BinderErrorKind::BadValue.into())
+    }
+    parcel.set_data_position(start + size as usize)?;
+    Ok(())
+}
@@ -16,8 +16,8 @@
 //! ## Version checking
 //!
 //! This module exposes the low-level primitives given to us by the kernel.
-//! The first thing to do when using those primitives is to check if the kernel
-//! version matches the implemented version.
+//! The first thing to do when using those primitives is to check which version
+//! of the protocol the kernel implements.
 //!
 //! ```
 //! use binder::sys;
@@ -25,11 +25,15 @@
 //! let binder = std::fs::File::open("/dev/binder").unwrap();
 //! let mut version = sys::binder_version { protocol_version: 0 }
 //! sys::binder_version(binder.as_raw_fd(), &mut binder_version);
-//! if version.protocol_version != sys::CurrentProtocolVersion {
-//!   panic!("Binder version mismatch")
-//! }
+//! let version = sys::ProtocolVersion::from_i32(version.protocol_version)
+//!     .expect("Unknown binder version");
 //! ```
 //!
+//! The two versions only differ by the size of `binder_size_t` and
+//! `binder_uintptr_t`, 32-bit in version 7 and 64-bit in version 8. The
+//! structures of this module use the version 8 layout. `ProtocolVersion`
+//! knows how to convert them to and from what the kernel actually uses.
+//!
 //! ## Communicating with binder_write_read
 //!
 //! The `binder_write_read` ioctl is used as a replacement of `read` and `write`
@@ -89,15 +93,22 @@
 //!
 //! ## Resource management
 //!
-//! TODO: Write the docs about
+//! The driver keeps strong and weak reference counts on every binder object.
+//!
+//! A process holding a handle tells the driver about the references it holds
+//! with the `IncRefs`/`DecRefs` (weak) and `Acquire`/`Release` (strong)
+//! commands. When the last reference to a handle goes away, the driver drops
+//! its reference to the underlying object.
+//!
+//! Conversely, the driver tells the process hosting an object when it takes
+//! or drops references to it, through the `IncRefs`, `Acquire`, `Release` and
+//! `DecRefs` return commands. The first two must be acknowledged with
+//! `IncRefsDone` and `AcquireDone`. As long as the driver holds a reference,
+//! the object must be kept alive.
 //!
-//! - IncRefs
-//! - Acquire
-//! - Release
-//! - DecRefs
-//! - IncRefsDone
-//! - DecRefsDone
-//! - AttemptAcquire
+//! Promoting a weak reference to a strong one is done with `AttemptAcquire`,
+//! which is answered by an `AcquireResult`. The Linux driver doesn't implement
+//! it, and rejects the command with `EINVAL`.
 //!
 //! ## Thread management
 //!
@@ -108,7 +119,13 @@
 //!
 //! ## Death notifications
 //!
-//! TODO: Request/Clear DeathNotification/ DeadBinder ???
+//! A process can ask to be notified when the process hosting one of its
+//! handles dies by sending `RequestDeathNotification` with the handle and a
+//! cookie of its choosing. When the remote process dies, the driver sends a
+//! `DeadBinder` with that cookie, which must be acknowledged with
+//! `DeadBinderDone`. A notification stays registered until it is cleared with
+//! `ClearDeathNotification`, which the driver acknowledges with
+//! `ClearDeathNotificationDone`.
 //!
 // TODO: Hyperlink the docs
 #![deny(missing_docs)]
@@ -117,25 +134,157 @@
 #![allow(bad_style)]
 
 use std::fmt::{Debug, Formatter, Error as FmtError};
+use std::io::{self, Read, Write};
 use std::mem::size_of;
 
-#[cfg(BINDER_IPC_32BIT)]
-pub type binder_size_t = u32;
-#[cfg(BINDER_IPC_32BIT)]
-pub type binder_uintptr_t = u32;
-#[cfg(not(BINDER_IPC_32BIT))]
+use byteorder::{ReadBytesExt, WriteBytesExt, NativeEndian};
+use libc::{pid_t, uid_t};
+use nix;
+
+/// A size, as sent to the kernel. Only 32-bit wide in version 7.
 pub type binder_size_t = u64;
-#[cfg(not(BINDER_IPC_32BIT))]
+/// A pointer, as sent to the kernel. Only 32-bit wide in version 7.
 pub type binder_uintptr_t = u64;
 
-use libc::{pid_t, uid_t};
+/// The versions of the binder protocol.
+///
+/// The version used is selected at kernel compile-time. 64-bit kernels always
+/// use version 8, while 32-bit kernels use version 7 unless they were built
+/// without `CONFIG_ANDROID_BINDER_IPC_32BIT`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ProtocolVersion {
+    /// The 32-bit protocol.
+    V7,
+    /// The 64-bit protocol.
+    V8
+}
+
+// The sizes of the structures in version 7.
+const TRANSACTION_DATA_V7_SIZE : usize = 40;
+const PTR_COOKIE_V7_SIZE : usize = 8;
+const HANDLE_COOKIE_V7_SIZE : usize = 8;
+const PRI_PTR_COOKIE_V7_SIZE : usize = 12;
+const UINTPTR_V7_SIZE : usize = 4;
+
+impl ProtocolVersion {
+    /// Get the version corresponding to what `binder_version` returned.
+    pub fn from_i32(version: i32) -> Option<ProtocolVersion> {
+        match version {
+            7 => Some(ProtocolVersion::V7),
+            8 => Some(ProtocolVersion::V8),
+            _ => None
+        }
+    }
+
+    /// The version number, as returned by `binder_version`.
+    pub fn as_i32(self) -> i32 {
+        match self {
+            ProtocolVersion::V7 => 7,
+            ProtocolVersion::V8 => 8
+        }
+    }
+
+    /// The size of `binder_size_t` and `binder_uintptr_t` in this version.
+    pub fn pointer_size(self) -> usize {
+        match self {
+            ProtocolVersion::V7 => 4,
+            ProtocolVersion::V8 => 8
+        }
+    }
 
-/// The version that the rust-binder library implements
-#[cfg(BINDER_IPC_32BIT)]
-pub const CurrentProtocolVersion: i32 = 7;
-/// The version that the rust-binder library implements
-#[cfg(not(BINDER_IPC_32BIT))]
-pub const CurrentProtocolVersion: i32 = 8;
+    /// The size of a `flat_binder_object` in this version.
+    pub fn flat_binder_object_size(self) -> usize {
+        8 + 2 * self.pointer_size()
+    }
+
+    /// Write a `binder_size_t` or `binder_uintptr_t`.
+    pub fn write_pointer<W: Write + ?Sized>(self, w: &mut W, val: binder_uintptr_t) -> io::Result<()> {
+        match self {
+            ProtocolVersion::V7 => w.write_u32::<NativeEndian>(val as u32),
+            ProtocolVersion::V8 => w.write_u64::<NativeEndian>(val)
+        }
+    }
+
+    /// Read a `binder_size_t` or `binder_uintptr_t`.
+    pub fn read_pointer<R: Read + ?Sized>(self, r: &mut R) -> io::Result<binder_uintptr_t> {
+        match self {
+            ProtocolVersion::V7 => r.read_u32::<NativeEndian>().map(|val| val as binder_uintptr_t),
+            ProtocolVersion::V8 => r.read_u64::<NativeEndian>()
+        }
+    }
+
+    /// Write a `binder_transaction_data`.
+    pub fn write_transaction_data<W: Write + ?Sized>(self, w: &mut W, txn: &binder_transaction_data) -> io::Result<()> {
+        // The handle shares the lower bits of the pointer.
+        self.write_pointer(w, unsafe { txn.target.ptr })?;
+        self.write_pointer(w, txn.cookie)?;
+        w.write_u32::<NativeEndian>(txn.code)?;
+        w.write_u32::<NativeEndian>(txn.flags)?;
+        w.write_i32::<NativeEndian>(txn.sender_pid)?;
+        w.write_u32::<NativeEndian>(txn.sender_euid)?;
+        self.write_pointer(w, txn.data_size)?;
+        self.write_pointer(w, txn.offsets_size)?;
+        self.write_pointer(w, txn.buffer)?;
+        self.write_pointer(w, txn.offsets)
+    }
+
+    /// Read a `binder_transaction_data`.
+    pub fn read_transaction_data<R: Read + ?Sized>(self, r: &mut R) -> io::Result<binder_transaction_data> {
+        let mut txn : binder_transaction_data = unsafe { ::std::mem::zeroed() };
+        txn.target.ptr = self.read_pointer(r)?;
+        txn.cookie = self.read_pointer(r)?;
+        txn.code = r.read_u32::<NativeEndian>()?;
+        txn.flags = r.read_u32::<NativeEndian>()?;
+        txn.sender_pid = r.read_i32::<NativeEndian>()?;
+        txn.sender_euid = r.read_u32::<NativeEndian>()?;
+        txn.data_size = self.read_pointer(r)?;
+        txn.offsets_size = self.read_pointer(r)?;
+        txn.buffer = self.read_pointer(r)?;
+        txn.offsets = self.read_pointer(r)?;
+        Ok(txn)
+    }
+
+    /// Read a `binder_ptr_cookie`.
+    pub fn read_ptr_cookie<R: Read + ?Sized>(self, r: &mut R) -> io::Result<binder_ptr_cookie> {
+        Ok(binder_ptr_cookie {
+            ptr: self.read_pointer(r)?,
+            cookie: self.read_pointer(r)?
+        })
+    }
+
+    /// Read a `binder_pri_ptr_cookie`.
+    pub fn read_pri_ptr_cookie<R: Read + ?Sized>(self, r: &mut R) -> io::Result<binder_pri_ptr_cookie> {
+        let priority = r.read_i32::<NativeEndian>()?;
+        if self == ProtocolVersion::V8 {
+            // Padding
+            r.read_u32::<NativeEndian>()?;
+        }
+        Ok(binder_pri_ptr_cookie {
+            priority: priority,
+            ptr: self.read_pointer(r)?,
+            cookie: self.read_pointer(r)?
+        })
+    }
+
+    /// Write a `flat_binder_object`.
+    pub fn write_flat_binder_object<W: Write + ?Sized>(self, w: &mut W, obj: &flat_binder_object) -> io::Result<()> {
+        w.write_u32::<NativeEndian>(obj.type_)?;
+        w.write_u32::<NativeEndian>(obj.flags)?;
+        // The handle shares the lower bits of the pointer.
+        self.write_pointer(w, unsafe { obj.target.binder })?;
+        self.write_pointer(w, obj.cookie)
+    }
+
+    /// Read a `flat_binder_object`.
+    pub fn read_flat_binder_object<R: Read + ?Sized>(self, r: &mut R) -> io::Result<flat_binder_object> {
+        let mut obj : flat_binder_object = unsafe { ::std::mem::zeroed() };
+        obj.type_ = r.read_u32::<NativeEndian>()?;
+        obj.flags = r.read_u32::<NativeEndian>()?;
+        obj.target.binder = self.read_pointer(r)?;
+        obj.cookie = self.read_pointer(r)?;
+        Ok(obj)
+    }
+}
 
 pub(crate) const fn pack_chars(c1: u8, c2: u8, c3: u8, c4: u8) -> u32 {
     ((((c1 as u32)<<24)) | (((c2 as u32)<<16)) | (((c3 as u32)<<8)) | (c4 as u32))
@@ -243,6 +392,28 @@ impl Clone for binder_write_read {
     fn clone(&self) -> Self { *self }
 }
 
+/// The version 7 layout of `binder_write_read`. See `write_read`.
+#[repr(C)]
+#[derive(Debug, Copy)]
+pub struct binder_write_read_v7 {
+    /// See `binder_write_read::write_size`.
+    pub write_size: u32,
+    /// See `binder_write_read::write_consumed`.
+    pub write_consumed: u32,
+    /// See `binder_write_read::write_buffer`.
+    pub write_buffer: u32,
+    /// See `binder_write_read::read_size`.
+    pub read_size: u32,
+    /// See `binder_write_read::read_consumed`.
+    pub read_consumed: u32,
+    /// See `binder_write_read::read_buffer`.
+    pub read_buffer: u32,
+}
+
+impl Clone for binder_write_read_v7 {
+    fn clone(&self) -> Self { *self }
+}
+
 /// A wrapper around an i32 specifying the implemented Protocol Version.
 #[repr(C)]
 #[derive(Debug, Copy)]
@@ -395,21 +566,28 @@ pub enum ReturnProtocol {
     /// A reply to the last transaction was received. Takes a
     /// `binder_transaction_data`.
     Reply = ior!('r', 3, size_of::<binder_transaction_data>()) as i32,
-    /// TODO: ???. Takes an `i32`.
+    /// The answer to an `AttemptAcquire` command. Takes an `i32`, non-zero if
+    /// the strong reference was granted.
     AcquireResult = ior!('r', 4, size_of::<i32>()) as i32,
     /// TODO: ???. Doesn't take any data.
     DeadReply = io!('r', 5) as i32,
     /// TODO: ???. Doesn't take any data.
     TransactionComplete = io!('r', 6) as i32,
-    /// TODO: ???. Takes a `binder_ptr_cookie`.
+    /// The driver took a weak reference to a local object. Takes a
+    /// `binder_ptr_cookie`. Must be acknowledged with `IncRefsDone`.
     IncRefs = ior!('r', 7, size_of::<binder_ptr_cookie>()) as i32,
-    /// TODO: ???. Takes a `binder_ptr_cookie`.
+    /// The driver took a strong reference to a local object. Takes a
+    /// `binder_ptr_cookie`. Must be acknowledged with `AcquireDone`.
     Acquire = ior!('r', 8, size_of::<binder_ptr_cookie>()) as i32,
-    /// TODO: ???. Takes a `binder_ptr_cookie`.
+    /// The driver dropped a strong reference to a local object. Takes a
+    /// `binder_ptr_cookie`.
     Release = ior!('r', 9, size_of::<binder_ptr_cookie>()) as i32,
-    /// TODO: ???. Takes a `binder_ptr_cookie`.
+    /// The driver dropped a weak reference to a local object. Takes a
+    /// `binder_ptr_cookie`.
     DecRefs = ior!('r', 10, size_of::<binder_ptr_cookie>()) as i32,
-    /// TODO: ???. Takes a `binder_pri_ptr_cookie`.
+    /// The driver asks whether it may take a strong reference to a local
+    /// object that may only be weakly referenced. Takes a
+    /// `binder_pri_ptr_cookie`. Must be answered with `AcquireResult`.
     AttemptAcquire = ior!('r', 11, size_of::<binder_pri_ptr_cookie>()) as i32,
     /// TODO: ???. Doesn't take any data.
     Noop = io!('r', 12) as i32,
@@ -417,37 +595,55 @@ pub enum ReturnProtocol {
     SpawnLooper = io!('r', 13) as i32,
     /// TODO: ???. Doesn't take any data.
     Finished = io!('r', 14) as i32,
-    /// TODO: ???. Takes a `binder_uintptr_t`.
+    /// The process hosting a binder we asked death notifications for died.
+    /// Takes a `binder_uintptr_t`, the cookie given to
+    /// `RequestDeathNotification`. Must be acknowledged with `DeadBinderDone`.
     DeadBinder = ior!('r', 15, size_of::<binder_uintptr_t>()) as i32,
-    /// TODO: ???. Takes a `binder_uintptr_t`.
+    /// A `ClearDeathNotification` was processed. Takes a `binder_uintptr_t`,
+    /// the cookie of the cleared notification.
     ClearDeathNotificationDone = ior!('r', 16, size_of::<binder_uintptr_t>()) as i32,
     /// TODO: ???. Doesn't take any data.
     FailedReply = io!('r', 17) as i32,
 }
 
 impl ReturnProtocol {
+    const ALL : [ReturnProtocol; 18] = [
+        ReturnProtocol::Error, ReturnProtocol::Ok, ReturnProtocol::Transaction,
+        ReturnProtocol::Reply, ReturnProtocol::AcquireResult,
+        ReturnProtocol::DeadReply, ReturnProtocol::TransactionComplete,
+        ReturnProtocol::IncRefs, ReturnProtocol::Acquire,
+        ReturnProtocol::Release, ReturnProtocol::DecRefs,
+        ReturnProtocol::AttemptAcquire, ReturnProtocol::Noop,
+        ReturnProtocol::SpawnLooper, ReturnProtocol::Finished,
+        ReturnProtocol::DeadBinder, ReturnProtocol::ClearDeathNotificationDone,
+        ReturnProtocol::FailedReply
+    ];
+
     /// Turns an i32 into its corresponding ReturnProtocol instance.
-    pub fn from_primitive(u: i32) -> Option<ReturnProtocol> {
+    pub fn from_primitive(u: i32, version: ProtocolVersion) -> Option<ReturnProtocol> {
+        ReturnProtocol::ALL.iter().cloned().find(|cmd| cmd.code(version) == u)
+    }
+
+    /// The value of this command in the given protocol version. The variants
+    /// hold the values of version 8, which differ from version 7 for the
+    /// commands whose data contain pointers.
+    pub fn code(self, version: ProtocolVersion) -> i32 {
         use self::ReturnProtocol::*;
-        if u == ior!('r', 0, size_of::<i32>()) as i32 { Some(Error) }
-        else if u == io!('r', 1) as i32 { Some(Ok) }
-        else if u == ior!('r', 2, size_of::<binder_transaction_data>()) as i32 { Some(Transaction) }
-        else if u == ior!('r', 3, size_of::<binder_transaction_data>()) as i32 { Some(Reply) }
-        else if u == ior!('r', 4, size_of::<i32>()) as i32 { Some(AcquireResult) }
-        else if u == io!('r', 5) as i32 { Some(DeadReply) }
-        else if u == io!('r', 6) as i32 { Some(TransactionComplete) }
-        else if u == ior!('r', 7, size_of::<binder_ptr_cookie>()) as i32 { Some(IncRefs) }
-        else if u == ior!('r', 8, size_of::<binder_ptr_cookie>()) as i32 { Some(Acquire) }
-        else if u == ior!('r', 9, size_of::<binder_ptr_cookie>()) as i32 { Some(Release) }
-        else if u == ior!('r', 10, size_of::<binder_ptr_cookie>()) as i32 { Some(DecRefs) }
-        else if u == ior!('r', 11, size_of::<binder_pri_ptr_cookie>()) as i32 { Some(AttemptAcquire) }
-        else if u == io!('r', 12) as i32 { Some(Noop) }
-        else if u == io!('r', 13) as i32 { Some(SpawnLooper) }
-        else if u == io!('r', 14) as i32 { Some(Finished) }
-        else if u == ior!('r', 15, size_of::<binder_uintptr_t>()) as i32 { Some(DeadBinder) }
-        else if u == ior!('r', 16, size_of::<binder_uintptr_t>()) as i32 { Some(ClearDeathNotificationDone) }
-        else if u == io!('r', 17) as i32 { Some(FailedReply) }
-        else { None }
+        if version == ProtocolVersion::V8 {
+            return self as i32
+        }
+        match self {
+            Transaction => ior!('r', 2, TRANSACTION_DATA_V7_SIZE) as i32,
+            Reply => ior!('r', 3, TRANSACTION_DATA_V7_SIZE) as i32,
+            IncRefs => ior!('r', 7, PTR_COOKIE_V7_SIZE) as i32,
+            Acquire => ior!('r', 8, PTR_COOKIE_V7_SIZE) as i32,
+            Release => ior!('r', 9, PTR_COOKIE_V7_SIZE) as i32,
+            DecRefs => ior!('r', 10, PTR_COOKIE_V7_SIZE) as i32,
+            AttemptAcquire => ior!('r', 11, PRI_PTR_COOKIE_V7_SIZE) as i32,
+            DeadBinder => ior!('r', 15, UINTPTR_V7_SIZE) as i32,
+            ClearDeathNotificationDone => ior!('r', 16, UINTPTR_V7_SIZE) as i32,
+            cmd => cmd as i32
+        }
     }
 }
 
@@ -478,7 +674,8 @@ pub enum CommandProtocol {
     /// `Transaction` had the `ONE_WAY` flag set. Otherwise, the kernel will
     /// assume that the thread is "Busy" and won't send more transactions to it.
     Reply = iow!('c', 1, size_of::<binder_transaction_data>()) as u32,
-    /// TODO: ???. Takes an i32
+    /// Answer an `AttemptAcquire` return command. Takes an `i32`, non-zero if
+    /// the strong reference was granted.
     AcquireResult = iow!('c', 2, size_of::<i32>()) as u32,
     /// Free a buffer acquired from a `Reply` response. Takes a
     /// `binder_uintptr_t`.
@@ -487,19 +684,24 @@ pub enum CommandProtocol {
     /// It will free both the buffer and the offsets array. Accessing them after
     /// freeing them is Undefined Behavior.
     FreeBuffer = iow!('c', 3, size_of::<binder_uintptr_t>()) as u32,
-    /// TODO: ???. Takes a `u32`
+    /// Take a weak reference to a handle. Takes a `u32`, the handle.
     IncRefs = iow!('c', 4, size_of::<u32>()) as u32,
-    /// TODO: ???. Takes a `u32`
+    /// Take a strong reference to a handle. Takes a `u32`, the handle.
     Acquire = iow!('c', 5, size_of::<u32>()) as u32,
-    /// TODO: ???. Takes a `u32`
+    /// Drop a strong reference to a handle. Takes a `u32`, the handle.
     Release = iow!('c', 6, size_of::<u32>()) as u32,
-    /// TODO: ???. Takes a `u32`
+    /// Drop a weak reference to a handle. Takes a `u32`, the handle.
     DecRefs = iow!('c', 7, size_of::<u32>()) as u32,
-    /// TODO: ???. Takes a `binder_ptr_cookie`
+    /// Acknowledge an `IncRefs` return command. Takes the `binder_ptr_cookie`
+    /// it contained.
     IncRefsDone = iow!('c', 8, size_of::<binder_ptr_cookie>()) as u32,
-    /// TODO: ???. Takes a `binder_ptr_cookie`
+    /// Acknowledge an `Acquire` return command. Takes the `binder_ptr_cookie`
+    /// it contained.
     AcquireDone = iow!('c', 9, size_of::<binder_ptr_cookie>()) as u32,
-    /// TODO: ???. Takes a `binder_pri_desc`
+    /// Attempt to promote a weak reference to a handle into a strong one.
+    /// Takes a `binder_pri_desc`. Answered with `AcquireResult`.
+    ///
+    /// Remnant of OpenBinder, not implemented by the Linux driver.
     AttemptAcquire = iow!('c', 10, size_of::<binder_pri_desc>()) as u32,
     /// Tells the kernel driver that the current thread entered the command handling
     /// loop. Doesn't take any additional data.
@@ -515,14 +717,41 @@ pub enum CommandProtocol {
     EnterLooper = io!('c', 12) as u32,
     /// TODO: ???. Doesn't take any additional data
     ExitLooper = io!('c', 13) as u32,
-    /// TODO: ???. Takes a `binder_handle_cookie`
+    /// Ask to be notified when the process hosting a handle dies. Takes a
+    /// `binder_handle_cookie`. The cookie will be sent back in the
+    /// `DeadBinder` notification.
     RequestDeathNotification = iow!('c', 14, size_of::<binder_handle_cookie>()) as u32,
-    /// TODO: ???. Takes a `binder_handle_cookie`
+    /// Cancel a death notification. Takes a `binder_handle_cookie`, which must
+    /// match the one given to `RequestDeathNotification`.
     ClearDeathNotification = iow!('c', 15, size_of::<binder_handle_cookie>()) as u32,
-    /// TODO: ???. Takes a `binder_uintptr_t`
+    /// Acknowledge a `DeadBinder` notification. Takes the `binder_uintptr_t`
+    /// cookie it contained.
     DeadBinderDone = iow!('c', 16, size_of::<binder_uintptr_t>()) as u32,
 }
 
+impl CommandProtocol {
+    /// The value of this command in the given protocol version. The variants
+    /// hold the values of version 8, which differ from version 7 for the
+    /// commands whose data contain pointers.
+    pub fn code(self, version: ProtocolVersion) -> u32 {
+        use self::CommandProtocol::*;
+        if version == ProtocolVersion::V8 {
+            return self as u32
+        }
+        match self {
+            Transaction => iow!('c', 0, TRANSACTION_DATA_V7_SIZE) as u32,
+            Reply => iow!('c', 1, TRANSACTION_DATA_V7_SIZE) as u32,
+            FreeBuffer => iow!('c', 3, UINTPTR_V7_SIZE) as u32,
+            IncRefsDone => iow!('c', 8, PTR_COOKIE_V7_SIZE) as u32,
+            AcquireDone => iow!('c', 9, PTR_COOKIE_V7_SIZE) as u32,
+            RequestDeathNotification => iow!('c', 14, HANDLE_COOKIE_V7_SIZE) as u32,
+            ClearDeathNotification => iow!('c', 15, HANDLE_COOKIE_V7_SIZE) as u32,
+            DeadBinderDone => iow!('c', 16, UINTPTR_V7_SIZE) as u32,
+            cmd => cmd as u32
+        }
+    }
+}
+
 // TODO: FromPrimitive for CommandProtocol
 
 ioctl! {
@@ -530,6 +759,39 @@ ioctl! {
     /// and potentially other binder clients.
     readwrite binder_write_read with b'b', 1; binder_write_read
 }
+ioctl! {
+    /// `binder_write_read` for version 7 of the protocol.
+    readwrite binder_write_read_v7 with b'b', 1; binder_write_read_v7
+}
+
+/// Call the `binder_write_read` ioctl matching `version`.
+///
+/// The consumed counts are updated even when the call fails, so that it can be
+/// resumed.
+///
+/// # Safety
+///
+/// The buffers in `bwr` have to be valid. In version 7, they also have to be
+/// addressable with 32 bits.
+pub unsafe fn write_read(fd: ::std::os::unix::io::RawFd, version: ProtocolVersion, bwr: &mut binder_write_read) -> nix::Result<()> {
+    match version {
+        ProtocolVersion::V8 => binder_write_read(fd, bwr).map(|_| ()),
+        ProtocolVersion::V7 => {
+            let mut bwr_v7 = binder_write_read_v7 {
+                write_size: bwr.write_size as u32,
+                write_consumed: bwr.write_consumed as u32,
+                write_buffer: bwr.write_buffer as u32,
+                read_size: bwr.read_size as u32,
+                read_consumed: bwr.read_consumed as u32,
+                read_buffer: bwr.read_buffer as u32
+            };
+            let res = binder_write_read_v7(fd, &mut bwr_v7);
+            bwr.write_consumed = bwr_v7.write_consumed as binder_size_t;
+            bwr.read_consumed = bwr_v7.read_consumed as binder_size_t;
+            res.map(|_| ())
+        }
+    }
+}
 ioctl! {
     /// TODO: Unused in kernel 4.9. Seems to be a remnant of openbinder, used in
     /// BeOS.
@@ -561,8 +823,9 @@ ioctl! {
 ioctl! {
     /// Sets the magic handle "0" to the current binder connection.
     ///
-    /// TODO: Figure out if arg is used, and if not how is it turned back into
-    /// a pointer (is it ?)
+    /// The argument is ignored. The context object gets a null ptr and cookie,
+    /// so the transactions sent to it have a null target. Prefer
+    /// `set_context_mgr_ext`, which lets us pick them.
     ///
     /// Should only be called once during the whole lifetime of the kernel driver.
     ///
@@ -595,8 +858,7 @@ ioctl! {
     write_ptr binder_thread_exit with b'b', 8; i32
 }
 ioctl! {
-    /// Ensure that the version of binder we implement is the same version as
-    /// the kernel.
+    /// Get the version of the binder protocol implemented by the kernel.
     ///
     /// The kernel will set the passed pointer to the currently implemented
     /// version.
@@ -605,12 +867,96 @@ ioctl! {
     /// The version used is selected at kernel compile-time depending on the
     /// architecture used. 64-bit targets use version 8, while 32-bit use version 7.
     ///
-    /// In rust-binder, the implemented version is stored in
-    /// the `CurrentProtocolVersion`. Depending on the `BINDER_IPC_32BIT` cfg,
-    /// it will either be version 7 or 8.
-    ///
-    /// TODO: Eventually, this will be exposed as a crate feature, or maybe even
-    /// dynamically figured out (which would be an improvement over libbinder). But
-    /// for now, we basically only allow version 7 (the 32-bit variant).
+    /// Unlike libbinder, which picks one at compile-time, we support both. See
+    /// `ProtocolVersion`. This ioctl has the same number in both versions.
     readwrite binder_version with b'b', 9; binder_version
 }
+
+/// The longest name a binderfs device can have, without the NUL terminator.
+pub const BINDERFS_MAX_NAME : usize = 255;
+
+/// Describes a binderfs device, for `binder_ctl_add`.
+#[repr(C)]
+#[derive(Copy)]
+pub struct binderfs_device {
+    /// The name of the device node to create, NUL-terminated.
+    pub name: [u8; BINDERFS_MAX_NAME + 1],
+    /// Set by the kernel to the major number of the new device.
+    pub major: u32,
+    /// Set by the kernel to the minor number of the new device.
+    pub minor: u32,
+}
+
+impl Clone for binderfs_device {
+    fn clone(&self) -> Self { *self }
+}
+
+impl Debug for binderfs_device {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(self.name.len());
+        f.debug_struct("binderfs_device")
+            .field("name", &String::from_utf8_lossy(&self.name[..len]))
+            .field("major", &self.major)
+            .field("minor", &self.minor)
+            .finish()
+    }
+}
+
+ioctl! {
+    /// Create a new binder device in a binderfs instance. Called on the
+    /// `binder-control` node at the root of the mount.
+    ///
+    /// The device node appears next to `binder-control`, and is removed by
+    /// unlinking it.
+    readwrite binder_ctl_add with b'b', 1; binderfs_device
+}
+
+/// The version 7 layout of `flat_binder_object`. See `set_context_mgr_ext`.
+#[repr(C)]
+#[derive(Debug, Copy)]
+pub struct flat_binder_object_v7 {
+    /// See `flat_binder_object::type_`.
+    pub type_: u32,
+    /// See `flat_binder_object::flags`.
+    pub flags: u32,
+    /// See `flat_binder_object::target`.
+    pub binder: u32,
+    /// See `flat_binder_object::cookie`.
+    pub cookie: u32,
+}
+
+impl Clone for flat_binder_object_v7 {
+    fn clone(&self) -> Self { *self }
+}
+
+ioctl! {
+    /// Like `binder_set_context_mgr`, but the context object is described by a
+    /// `flat_binder_object`, letting us choose its ptr, cookie and flags.
+    ///
+    /// Added in Linux 4.14. Older kernels fail with `EINVAL`.
+    write_ptr binder_set_context_mgr_ext with b'b', 13; flat_binder_object
+}
+ioctl! {
+    /// `binder_set_context_mgr_ext` for version 7 of the protocol.
+    write_ptr binder_set_context_mgr_ext_v7 with b'b', 13; flat_binder_object_v7
+}
+
+/// Call the `binder_set_context_mgr_ext` ioctl matching `version`.
+///
+/// # Safety
+///
+/// Same as `binder_set_context_mgr_ext`.
+pub unsafe fn set_context_mgr_ext(fd: ::std::os::unix::io::RawFd, version: ProtocolVersion, obj: &flat_binder_object) -> nix::Result<()> {
+    match version {
+        ProtocolVersion::V8 => binder_set_context_mgr_ext(fd, obj).map(|_| ()),
+        ProtocolVersion::V7 => {
+            let obj_v7 = flat_binder_object_v7 {
+                type_: obj.type_,
+                flags: obj.flags,
+                binder: obj.target.binder as u32,
+                cookie: obj.cookie as u32
+            };
+            binder_set_context_mgr_ext_v7(fd, &obj_v7).map(|_| ())
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! These tests create their own binder context with binderfs, which requires
+//! root and a kernel built with `CONFIG_ANDROID_BINDERFS`. Run them with
+//! `cargo test -- --ignored`.
+
+extern crate binder;
+
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use binder::{BinderConnection, Handle, IBinder, IInterface};
+use binder::binderfs::BinderFs;
+use binder::error::BinderResult;
+use binder::local_binder::LocalBinder;
+use binder::parcel::{Parcel, OwnedParcel};
+use binder::service_manager::{ServiceManager, DUMP_FLAG_PRIORITY_ALL, DUMP_FLAG_PRIORITY_DEFAULT};
+
+const DESCRIPTOR : &str = "binder.test.IEcho";
+
+/// Tells `dying_client` which device to use, when it runs in a child process.
+const CLIENT_DEVICE_ENV : &str = "BINDER_TEST_CLIENT_DEVICE";
+
+struct Echo;
+
+impl LocalBinder for Echo {
+    fn get_interface_descriptor(&self) -> &str {
+        DESCRIPTOR
+    }
+
+    fn on_transact(&self, _code: u32, data: &mut Parcel, reply: &mut OwnedParcel, _flags: u32) -> BinderResult<()> {
+        data.enforce_interface(DESCRIPTOR)?;
+        let val = data.read_i32()?;
+        reply.write_i32(val);
+        Ok(())
+    }
+}
+
+struct BpEcho {
+    handle: Rc<RefCell<Handle>>
+}
+
+impl IInterface for BpEcho {
+    fn get_interface_descriptor() -> &'static str {
+        DESCRIPTOR
+    }
+
+    fn from_handle(handle: Rc<RefCell<Handle>>) -> BpEcho {
+        BpEcho { handle: handle }
+    }
+}
+
+impl BpEcho {
+    fn echo(&self, val: i32) -> BinderResult<i32> {
+        let handle = self.handle.borrow();
+        let mut data = OwnedParcel::new(handle.conn().clone());
+        data.write_interface_token(DESCRIPTOR);
+        data.write_i32(val);
+        let mut reply = handle.transact(binder::FIRST_CALL_TRANSACTION, &mut data, 0)?;
+        Ok(reply.read_i32()?)
+    }
+}
+
+/// Mount a private binderfs instance with a single device, and run a
+/// servicemanager on it.
+fn setup(name: &str) -> (BinderFs, PathBuf) {
+    let root = env::temp_dir().join(format!("binderfs-{}-{}", name, process::id()));
+    let fs = BinderFs::mount(&root).unwrap();
+    let device = fs.add_device("binder").unwrap();
+    let sm_device = device.clone();
+    thread::spawn(move || {
+        let mut conn = open(&sm_device);
+        binder::servicemanager::run(&mut conn).unwrap();
+    });
+    (fs, device)
+}
+
+fn open(device: &Path) -> BinderConnection {
+    BinderConnection::builder().device(device).open().unwrap()
+}
+
+/// Get the servicemanager, waiting for it to start.
+fn service_manager(conn: &mut BinderConnection) -> ServiceManager {
+    for _ in 0..50 {
+        if let Some(mut sm) = conn.get_service_manager() {
+            if sm.list_services(DUMP_FLAG_PRIORITY_ALL).is_ok() {
+                return sm
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("The servicemanager didn't start");
+}
+
+#[test]
+#[ignore]
+fn servicemanager_on_binderfs() {
+    let (_fs, device) = setup("servicemanager");
+
+    // The service lives in its own process, as far as the driver is
+    // concerned.
+    let server_device = device.clone();
+    thread::spawn(move || {
+        let mut conn = open(&server_device);
+        let echo : Arc<LocalBinder> = Arc::new(Echo);
+        service_manager(&mut conn).add_service("echo", IBinder::Local(echo), false, DUMP_FLAG_PRIORITY_DEFAULT).unwrap();
+        conn.join_thread_pool(true).unwrap();
+    });
+
+    let mut conn = open(&device);
+    let mut sm = service_manager(&mut conn);
+    let echo = loop {
+        if let Some(echo) = sm.check_service::<BpEcho>("echo").unwrap() {
+            break echo
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(echo.echo(42).unwrap(), 42);
+    assert!(sm.check_service::<BpEcho>("missing").unwrap().is_none());
+    assert_eq!(sm.list_services(DUMP_FLAG_PRIORITY_ALL).unwrap(), vec!["echo".to_string()]);
+}
+
+/// Not a test by itself: `servicemanager_survives_dying_clients` runs it in a
+/// child process, which it kills while it talks to the servicemanager.
+#[test]
+#[ignore]
+fn dying_client() {
+    let device = match env::var_os(CLIENT_DEVICE_ENV) {
+        Some(device) => PathBuf::from(device),
+        None => return
+    };
+    let mut conn = open(&device);
+    let mut sm = service_manager(&mut conn);
+    let echo : Arc<LocalBinder> = Arc::new(Echo);
+    sm.add_service("dying", IBinder::Local(echo), false, DUMP_FLAG_PRIORITY_DEFAULT).unwrap();
+    loop {
+        sm.list_services(DUMP_FLAG_PRIORITY_ALL).unwrap();
+    }
+}
+
+#[test]
+#[ignore]
+fn servicemanager_survives_dying_clients() {
+    let (_fs, device) = setup("dying");
+    let mut conn = open(&device);
+    let mut sm = service_manager(&mut conn);
+
+    for i in 0..20 {
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(&["--exact", "dying_client", "--ignored"])
+            .env(CLIENT_DEVICE_ENV, &device)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        // Vary the timing, so the client dies at different points of its
+        // calls.
+        thread::sleep(Duration::from_millis(100 + i * 7));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    // The servicemanager still answers, and forgot about the dead service.
+    for _ in 0..50 {
+        if !sm.list_services(DUMP_FLAG_PRIORITY_ALL).unwrap().contains(&"dying".to_string()) {
+            return
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("The service of the dead client is still registered");
+}
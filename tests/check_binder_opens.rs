@@ -8,5 +8,5 @@ fn it_works() {
     println!("Getting service manager object");
     let mut svcmgr = binder.get_service_manager().unwrap();
     println!("Listing services");
-    println!("{:?}", svcmgr.list_services());
+    println!("{:?}", svcmgr.list_services(binder::service_manager::DUMP_FLAG_PRIORITY_ALL).unwrap());
 }
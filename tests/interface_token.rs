@@ -0,0 +1,44 @@
+extern crate binder;
+
+use binder::BinderConnection;
+use binder::error::{BinderError, BinderErrorKind};
+use binder::parcel::{Parcel, OwnedParcel, InterfaceTokenFormat, INTERFACE_HEADER_SYSTEM, INTERFACE_HEADER_VENDOR};
+
+const DESCRIPTOR : &str = "android.os.IServiceManager";
+
+#[test]
+fn enforce_every_format() {
+    let formats = [
+        InterfaceTokenFormat::Legacy,
+        InterfaceTokenFormat::WorkSource,
+        InterfaceTokenFormat::Header(INTERFACE_HEADER_SYSTEM),
+        InterfaceTokenFormat::Header(INTERFACE_HEADER_VENDOR),
+    ];
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    for &format in formats.iter() {
+        parcel.clear();
+        parcel.write_interface_token_as(DESCRIPTOR, format);
+        parcel.write_i32(42);
+        parcel.set_position(0);
+        parcel.enforce_interface(DESCRIPTOR).unwrap();
+        assert_eq!(parcel.read_i32().unwrap(), 42);
+
+        parcel.set_position(0);
+        match parcel.enforce_interface("android.os.IOther") {
+            Err(BinderError(BinderErrorKind::BadType, _)) => (),
+            res => panic!("{:?} token for another interface was accepted: {:?}", format, res)
+        }
+    }
+}
+
+#[test]
+fn token_layout() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.write_interface_token_as(DESCRIPTOR, InterfaceTokenFormat::Header(INTERFACE_HEADER_SYSTEM));
+    parcel.set_position(0);
+    // Strict-mode policy, unset work source, then the header.
+    assert_eq!(parcel.read_i32().unwrap(), 0);
+    assert_eq!(parcel.read_i32().unwrap(), -1);
+    assert_eq!(parcel.read_u32().unwrap(), 0x53595354);
+    assert_eq!(parcel.read_string16().unwrap(), DESCRIPTOR);
+}
@@ -0,0 +1,201 @@
+extern crate binder;
+extern crate nix;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+use binder::BinderConnection;
+use binder::error::{BinderError, BinderErrorKind};
+use binder::parcel::{Parcel, ParcelVectorExt, OwnedParcel};
+
+fn pipe() -> (File, File) {
+    let (read, write) = nix::unistd::pipe().unwrap();
+    unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) }
+}
+
+/// Whether every write end of the pipe was closed.
+fn is_closed(read: &mut File) -> bool {
+    let mut buf = [0; 16];
+    read.read(&mut buf).unwrap() == 0
+}
+
+#[test]
+fn fd_round_trip() {
+    let (mut read, mut write) = pipe();
+    {
+        let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+        parcel.write_dup_file_descriptor(&write).unwrap();
+        parcel.set_position(0);
+        let mut received = parcel.read_file_descriptor().unwrap();
+        assert!(received.as_raw_fd() != write.as_raw_fd());
+        received.write_all(b"parcel").unwrap();
+    }
+    // The parcel only closed its duplicate.
+    write.write_all(b" caller").unwrap();
+    drop(write);
+    let mut content = String::new();
+    read.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "parcel caller");
+}
+
+#[test]
+fn fd_closed_on_drop() {
+    let (mut read, write) = pipe();
+    {
+        let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+        parcel.write_file_descriptor(write).unwrap();
+        parcel.set_position(0);
+        // Reading gives us a duplicate, the parcel keeps its own.
+        drop(parcel.read_file_descriptor().unwrap());
+    }
+    assert!(is_closed(&mut read));
+}
+
+#[test]
+fn fds_not_allowed() {
+    let (mut read, write) = pipe();
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.set_allow_fds(false);
+    assert!(!parcel.allow_fds());
+    match parcel.write_file_descriptor(write) {
+        Err(BinderError(BinderErrorKind::FdsNotAllowed, _)) => (),
+        res => panic!("Fd written to a parcel that doesn't allow them: {:?}", res)
+    }
+    assert_eq!(parcel.len(), 0);
+    // The rejected fd isn't leaked.
+    assert!(is_closed(&mut read));
+}
+
+#[test]
+fn primitive_round_trip() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    for &val in [i8::min_value(), -1, 0, i8::max_value()].iter() {
+        parcel.write_i8(val);
+    }
+    for &val in [0, u8::max_value()].iter() {
+        parcel.write_u8(val);
+    }
+    parcel.write_bool(true);
+    parcel.write_bool(false);
+    parcel.write_char(0xffff);
+    for &val in [i32::min_value(), -1, i32::max_value()].iter() {
+        parcel.write_i32(val);
+    }
+    parcel.write_u32(u32::max_value());
+    for &val in [i64::min_value(), -1, i64::max_value()].iter() {
+        parcel.write_i64(val);
+    }
+    parcel.write_u64(u64::max_value());
+    parcel.write_f32(-1.5);
+    parcel.write_f64(std::f64::consts::PI);
+    // Everything narrower than 32 bits takes an int32.
+    assert_eq!(parcel.len(), 4 * 9 + 4 * 4 + 8 * 4 + 4 + 8);
+
+    parcel.set_position(0);
+    for &val in [i8::min_value(), -1, 0, i8::max_value()].iter() {
+        assert_eq!(parcel.read_i8().unwrap(), val);
+    }
+    for &val in [0, u8::max_value()].iter() {
+        assert_eq!(parcel.read_u8().unwrap(), val);
+    }
+    assert_eq!(parcel.read_bool().unwrap(), true);
+    assert_eq!(parcel.read_bool().unwrap(), false);
+    assert_eq!(parcel.read_char().unwrap(), 0xffff);
+    for &val in [i32::min_value(), -1, i32::max_value()].iter() {
+        assert_eq!(parcel.read_i32().unwrap(), val);
+    }
+    assert_eq!(parcel.read_u32().unwrap(), u32::max_value());
+    for &val in [i64::min_value(), -1, i64::max_value()].iter() {
+        assert_eq!(parcel.read_i64().unwrap(), val);
+    }
+    assert_eq!(parcel.read_u64().unwrap(), u64::max_value());
+    assert_eq!(parcel.read_f32().unwrap(), -1.5);
+    assert_eq!(parcel.read_f64().unwrap(), std::f64::consts::PI);
+    assert!(!parcel.has_data());
+}
+
+#[test]
+fn narrow_primitives_are_sign_extended() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.write_i8(-2);
+    parcel.write_u8(0xfe);
+    parcel.write_char(0xfffe);
+    parcel.set_position(0);
+    // Like libbinder, signed bytes keep their sign in the int32.
+    assert_eq!(parcel.read_i32().unwrap(), -2);
+    assert_eq!(parcel.read_i32().unwrap(), 0xfe);
+    assert_eq!(parcel.read_i32().unwrap(), 0xfffe);
+}
+
+#[test]
+fn vector_round_trip() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.write_nullable_i32_vector(None);
+    parcel.write_i32_vector(&[]);
+    parcel.write_i32_vector(&[i32::min_value(), 0, i32::max_value()]);
+    parcel.write_nullable_i64_vector(Some(&[-1, 1]));
+    parcel.write_bool_vector(&[true, false]);
+    parcel.write_nullable_byte_vector(None);
+    parcel.write_byte_vector(&[]);
+    parcel.write_byte_vector(&[1, 2, 3, 4, 5]);
+    parcel.write_nullable_string16_vector::<&str>(None);
+    parcel.write_string16_vector::<&str>(&[]);
+    parcel.write_string16_vector(&["a", "bc"]);
+    parcel.write_string8_vector(&["utf8"]);
+    // Elements of string arrays may be null.
+    parcel.write_vector_with(&[Some("x"), None], |p, s| p.write_nullable_string16(*s));
+
+    parcel.set_position(0);
+    assert_eq!(parcel.read_nullable_i32_vector().unwrap(), None);
+    assert_eq!(parcel.read_i32_vector().unwrap(), vec![]);
+    assert_eq!(parcel.read_i32_vector().unwrap(), vec![i32::min_value(), 0, i32::max_value()]);
+    assert_eq!(parcel.read_nullable_i64_vector().unwrap(), Some(vec![-1, 1]));
+    assert_eq!(parcel.read_bool_vector().unwrap(), vec![true, false]);
+    assert_eq!(parcel.read_nullable_byte_vector().unwrap(), None);
+    assert_eq!(parcel.read_byte_vector().unwrap(), vec![]);
+    assert_eq!(parcel.read_byte_vector().unwrap(), vec![1, 2, 3, 4, 5]);
+    assert_eq!(parcel.read_nullable_string16_vector().unwrap(), None);
+    assert_eq!(parcel.read_string16_vector().unwrap(), vec![]);
+    assert_eq!(parcel.read_string16_vector().unwrap(), vec![Some("a".to_string()), Some("bc".to_string())]);
+    assert_eq!(parcel.read_string8_vector().unwrap(), vec![Some("utf8".to_string())]);
+    assert_eq!(parcel.read_string16_vector().unwrap(), vec![Some("x".to_string()), None]);
+    assert!(!parcel.has_data());
+}
+
+#[test]
+fn null_vector_rejected() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.write_nullable_i32_vector(None);
+    parcel.set_position(0);
+    assert!(parcel.read_i32_vector().is_err());
+}
+
+#[test]
+fn truncated_vector() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    // Claims three elements, but only has room for two.
+    parcel.write_i32(3);
+    parcel.write_i32(1);
+    parcel.write_i32(2);
+    parcel.set_position(0);
+    match parcel.read_i32_vector().map_err(BinderError::from) {
+        Err(BinderError(BinderErrorKind::BadValue, _)) => (),
+        res => panic!("Truncated array was accepted: {:?}", res)
+    }
+
+    parcel.set_position(0);
+    match parcel.read_vector_with(4, |p| p.read_i32().map_err(BinderError::from)) {
+        Err(BinderError(BinderErrorKind::BadValue, _)) => (),
+        res => panic!("Truncated array was accepted: {:?}", res)
+    }
+
+    // Negative lengths other than -1 are invalid.
+    parcel.clear();
+    parcel.write_i32(-2);
+    parcel.set_position(0);
+    match parcel.read_nullable_byte_vector().map_err(BinderError::from) {
+        Err(BinderError(BinderErrorKind::BadValue, _)) => (),
+        res => panic!("Negative array length was accepted: {:?}", res)
+    }
+}
@@ -0,0 +1,123 @@
+extern crate binder;
+extern crate byteorder;
+
+use std::io::Cursor;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use binder::sys::{self, ProtocolVersion, binder_transaction_data, flat_binder_object};
+
+fn transaction() -> binder_transaction_data {
+    let mut txn : binder_transaction_data = unsafe { std::mem::zeroed() };
+    txn.target.ptr = 0x11;
+    txn.cookie = 0x22;
+    txn.code = 0x33;
+    txn.flags = 0x44;
+    txn.sender_pid = 0x55;
+    txn.sender_euid = 0x66;
+    txn.data_size = 0x77;
+    txn.offsets_size = 0x88;
+    txn.buffer = 0x99;
+    txn.offsets = 0xaa;
+    txn
+}
+
+fn object() -> flat_binder_object {
+    let mut obj : flat_binder_object = unsafe { std::mem::zeroed() };
+    obj.type_ = sys::BinderType::Binder as u32;
+    obj.flags = 0x17f;
+    obj.target.binder = 0x1234;
+    obj.cookie = 0x5678;
+    obj
+}
+
+/// Read the field at `offset`, which is `size` bytes wide.
+fn field(buf: &[u8], offset: usize, size: usize) -> u64 {
+    match size {
+        4 => NativeEndian::read_u32(&buf[offset..]) as u64,
+        8 => NativeEndian::read_u64(&buf[offset..]),
+        _ => unreachable!()
+    }
+}
+
+fn check_transaction_data(version: ProtocolVersion, size: usize, offsets: &[usize]) {
+    let mut buf = Vec::new();
+    version.write_transaction_data(&mut buf, &transaction()).unwrap();
+    assert_eq!(buf.len(), size);
+
+    let ptr = version.pointer_size();
+    let widths = [ptr, ptr, 4, 4, 4, 4, ptr, ptr, ptr, ptr];
+    let values = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa];
+    for ((&offset, &width), &value) in offsets.iter().zip(widths.iter()).zip(values.iter()) {
+        assert_eq!(field(&buf, offset, width), value, "field at {} in version {}", offset, version.as_i32());
+    }
+
+    let txn = version.read_transaction_data(&mut Cursor::new(&buf)).unwrap();
+    assert_eq!(unsafe { txn.target.ptr }, 0x11);
+    assert_eq!(txn.cookie, 0x22);
+    assert_eq!(txn.code, 0x33);
+    assert_eq!(txn.flags, 0x44);
+    assert_eq!(txn.sender_pid, 0x55);
+    assert_eq!(txn.sender_euid, 0x66);
+    assert_eq!(txn.data_size, 0x77);
+    assert_eq!(txn.offsets_size, 0x88);
+    assert_eq!(txn.buffer, 0x99);
+    assert_eq!(txn.offsets, 0xaa);
+}
+
+fn check_flat_binder_object(version: ProtocolVersion, size: usize, offsets: &[usize]) {
+    let mut buf = Vec::new();
+    version.write_flat_binder_object(&mut buf, &object()).unwrap();
+    assert_eq!(buf.len(), size);
+    assert_eq!(version.flat_binder_object_size(), size);
+
+    let ptr = version.pointer_size();
+    let widths = [4, 4, ptr, ptr];
+    let values = [sys::BinderType::Binder as u64, 0x17f, 0x1234, 0x5678];
+    for ((&offset, &width), &value) in offsets.iter().zip(widths.iter()).zip(values.iter()) {
+        assert_eq!(field(&buf, offset, width), value, "field at {} in version {}", offset, version.as_i32());
+    }
+
+    let obj = version.read_flat_binder_object(&mut Cursor::new(&buf)).unwrap();
+    assert_eq!(obj.type_, sys::BinderType::Binder as u32);
+    assert_eq!(obj.flags, 0x17f);
+    assert_eq!(unsafe { obj.target.binder }, 0x1234);
+    assert_eq!(obj.cookie, 0x5678);
+}
+
+#[test]
+fn transaction_data_v7() {
+    check_transaction_data(ProtocolVersion::V7, 40, &[0, 4, 8, 12, 16, 20, 24, 28, 32, 36]);
+}
+
+#[test]
+fn transaction_data_v8() {
+    check_transaction_data(ProtocolVersion::V8, 64, &[0, 8, 16, 20, 24, 28, 32, 40, 48, 56]);
+    assert_eq!(std::mem::size_of::<binder_transaction_data>(), 64);
+}
+
+#[test]
+fn flat_binder_object_v7() {
+    check_flat_binder_object(ProtocolVersion::V7, 16, &[0, 4, 8, 12]);
+}
+
+#[test]
+fn flat_binder_object_v8() {
+    check_flat_binder_object(ProtocolVersion::V8, 24, &[0, 4, 8, 16]);
+    assert_eq!(std::mem::size_of::<flat_binder_object>(), 24);
+}
+
+#[test]
+fn truncated_structures() {
+    for &version in [ProtocolVersion::V7, ProtocolVersion::V8].iter() {
+        let mut buf = Vec::new();
+        version.write_transaction_data(&mut buf, &transaction()).unwrap();
+        buf.pop();
+        assert!(version.read_transaction_data(&mut Cursor::new(&buf)).is_err());
+
+        let mut buf = Vec::new();
+        version.write_flat_binder_object(&mut buf, &object()).unwrap();
+        buf.pop();
+        assert!(version.read_flat_binder_object(&mut Cursor::new(&buf)).is_err());
+    }
+}
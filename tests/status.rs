@@ -0,0 +1,67 @@
+extern crate binder;
+
+use binder::BinderConnection;
+use binder::parcel::{Parcel, OwnedParcel};
+use binder::status::{ExceptionCode, Status};
+
+fn round_trip(status: &Status) -> Status {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.write_exception(status);
+    // Make sure the whole header is consumed.
+    parcel.write_i32(42);
+    parcel.set_position(0);
+    let read = parcel.read_exception().unwrap();
+    assert_eq!(parcel.read_i32().unwrap(), 42);
+    read
+}
+
+#[test]
+fn status_round_trip() {
+    let statuses = [
+        Status::ok(),
+        Status::from_exception(ExceptionCode::IllegalArgument, "bad argument"),
+        Status::from_exception(ExceptionCode::Parcelable, "with a parcelable"),
+        Status::from_service_specific_error(-7, "service error"),
+    ];
+    for status in statuses.iter() {
+        assert_eq!(&round_trip(status), status);
+    }
+}
+
+#[test]
+fn status_parcelable_header() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    parcel.write_exception(&Status::from_exception(ExceptionCode::Parcelable, ""));
+    parcel.set_position(0);
+    assert_eq!(parcel.read_i32().unwrap(), ExceptionCode::Parcelable.to_i32());
+    assert_eq!(parcel.read_string16().unwrap(), "");
+    // Empty stack trace.
+    assert_eq!(parcel.read_i32().unwrap(), 0);
+    // The parcelable's size includes its own header.
+    assert_eq!(parcel.read_i32().unwrap(), 4);
+    assert_eq!(parcel.data_position(), parcel.len());
+}
+
+#[test]
+fn status_reply_headers() {
+    let mut parcel = OwnedParcel::new(BinderConnection::open().unwrap());
+    // A fat reply header, which means there is no exception.
+    parcel.write_i32(-128);
+    parcel.write_i32(8);
+    parcel.write_i32(0);
+    parcel.write_i32(42);
+    parcel.set_position(0);
+    assert!(parcel.read_exception().unwrap().is_ok());
+    assert_eq!(parcel.read_i32().unwrap(), 42);
+
+    // Noted app ops come before the actual exception.
+    parcel.clear();
+    parcel.write_i32(-127);
+    parcel.write_i32(8);
+    parcel.write_i32(0);
+    Status::from_exception(ExceptionCode::Security, "denied").write_to_parcel(&mut parcel);
+    parcel.set_position(0);
+    let status = parcel.read_exception().unwrap();
+    assert_eq!(status.exception_code(), ExceptionCode::Security);
+    assert_eq!(status.message(), "denied");
+}